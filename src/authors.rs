@@ -0,0 +1,134 @@
+use std::{cmp::Reverse, collections::HashMap, fs, path::Path};
+
+use log::warn;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    AppError,
+    count::{
+        Config, Counts, FileCounts, OutputCounts, count_lines_by_owner, display_path, is_test_file,
+        make_pbar,
+    },
+    git::{self, Blame, GitError, NOT_COMMITTED},
+    languages::LanguageId,
+};
+
+/// The lines of one language last changed by one author
+#[derive(Serialize)]
+pub struct AuthorCounts {
+    pub author: String,
+    #[serde(skip)]
+    pub lang_id: LanguageId,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+// files that aren't tracked can't be blamed, all their lines are uncommitted, any other
+// failure is an error, `rel_path` is relative to `dir`
+fn blame_file(
+    file: &FileCounts,
+    dir: &Path,
+    rel_path: &Path,
+    config: &Config,
+) -> Result<Blame, AppError> {
+    match git::blame(dir, rel_path, config.rev.as_deref()) {
+        Ok(blame) => Ok(blame),
+        Err(err @ GitError::Command { .. })
+            if config.rev.is_none() && !git::is_tracked(dir, rel_path)? =>
+        {
+            warn!("can't blame untracked file {:?}: {err}", file.path);
+            let contents = fs::read(&file.path)?;
+            let num_lines = contents.split(|b| *b == b'\n').count();
+            Ok(Blame {
+                authors: vec![NOT_COMMITTED.to_string(); num_lines],
+                contents,
+            })
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// blames every counted file, the counts of a file are split between the authors of
+// its lines
+pub fn make_author_counts(
+    output: &OutputCounts,
+    config: &Config,
+) -> Result<Vec<AuthorCounts>, AppError> {
+    let pbar = make_pbar(config);
+    // git can't run in a file, a single counted file is blamed from its directory
+    let dir = match config.abs_root.parent() {
+        Some(parent) if !config.abs_root.is_dir() => parent,
+        _ => config.abs_root.as_path(),
+    };
+
+    let by_file = output
+        .files
+        .par_iter()
+        .map(|file| {
+            if let Some(pbar) = &pbar {
+                pbar.inc(1);
+                pbar.set_message(display_path(&file.path, config));
+            }
+
+            let rel_path = file.path.strip_prefix(dir).unwrap_or(&file.path);
+            let blame = blame_file(file, dir, rel_path, config)?;
+
+            let lang = &config.languages[file.lang_id];
+            let dirs = rel_path.parent().into_iter().flat_map(Path::iter);
+            let owners = blame.authors.iter().map(String::as_str).collect::<Vec<_>>();
+            let counts = count_lines_by_owner(
+                blame.contents.as_slice(),
+                lang,
+                is_test_file(dirs, lang),
+                config,
+                &owners,
+            );
+            let counts = counts
+                .into_iter()
+                .map(|(author, counts)| (author.to_string(), counts))
+                .collect::<Vec<_>>();
+            Ok((file.lang_id, counts))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    if let Some(pbar) = &pbar {
+        pbar.finish_and_clear();
+    }
+
+    let mut authors = HashMap::<(String, LanguageId), Counts>::new();
+    for (lang_id, counts) in by_file {
+        for (author, counts) in counts {
+            authors.entry((author, lang_id)).or_default().merge(&counts);
+        }
+    }
+
+    Ok(authors
+        .into_iter()
+        .map(|((author, lang_id), counts)| AuthorCounts {
+            author,
+            lang_id,
+            counts,
+        })
+        .collect())
+}
+
+// reverse order by number of code lines of the author and then of the language, forward
+// order by author and language
+pub fn sort_authors(authors: &[AuthorCounts]) -> Vec<&AuthorCounts> {
+    let mut totals = HashMap::<&str, usize>::new();
+    for author in authors {
+        *totals.entry(&author.author).or_default() += author.counts.code;
+    }
+
+    let mut sorted_authors = authors.iter().collect::<Vec<_>>();
+    sorted_authors.sort_by_key(|author| {
+        (
+            Reverse(totals[author.author.as_str()]),
+            &author.author,
+            Reverse(author.counts.code),
+            author.lang_id,
+        )
+    });
+    sorted_authors
+}
@@ -0,0 +1,396 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    AppError, DepKind,
+    count::{Config, Counts, OutputCounts, run_count_in},
+};
+
+#[derive(Error, Debug)]
+pub enum CargoError {
+    #[error("failed to run cargo")]
+    Io(#[from] std::io::Error),
+
+    #[error("cargo metadata failed: {0}")]
+    Metadata(String),
+
+    #[error("serde json error")]
+    SerdeJson(#[from] serde_json::Error),
+}
+
+// the subset of `cargo metadata --format-version 1` that we use
+
+#[derive(Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+    pub resolve: Option<Resolve>, // `None` with `--no-deps`
+}
+
+#[derive(Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    pub dep_kinds: Vec<NodeDepKind>,
+}
+
+#[derive(Deserialize)]
+pub struct NodeDepKind {
+    pub kind: Option<String>, // `None` for normal dependencies
+}
+
+#[derive(Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+impl Package {
+    pub fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+impl Target {
+    fn kind(&self) -> &'static str {
+        match self.kind.first().map(String::as_str) {
+            Some("bin") => "bin",
+            Some("example") => "example",
+            Some("test") => "test",
+            Some("bench") => "bench",
+            Some("custom-build") => "build",
+            // lib, rlib, dylib, cdylib, staticlib, proc-macro
+            _ => "lib",
+        }
+    }
+}
+
+fn cargo() -> Command {
+    // when running as a cargo subcommand, `CARGO` points to the cargo binary in use
+    Command::new(env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")))
+}
+
+// never touches the network, dependencies must already be downloaded or vendored
+pub fn metadata(dir: &Path, no_deps: bool) -> Result<Metadata, CargoError> {
+    let mut command = cargo();
+    command
+        .args(["metadata", "--format-version", "1", "--offline"])
+        .current_dir(dir);
+    if no_deps {
+        command.arg("--no-deps");
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CargoError::Metadata(stderr.trim().to_string()));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// The member packages of the workspace being analyzed
+pub struct Workspace {
+    pub root: PathBuf,
+    pub members: Vec<Package>,
+}
+
+impl Workspace {
+    pub fn load(dir: &Path) -> Result<Self, CargoError> {
+        let Metadata {
+            packages,
+            workspace_members,
+            workspace_root,
+            ..
+        } = metadata(dir, true)?;
+
+        let members = packages
+            .into_iter()
+            .filter(|package| workspace_members.contains(&package.id))
+            .collect();
+
+        Ok(Self {
+            root: workspace_root,
+            members,
+        })
+    }
+
+    // the innermost package containing `path`
+    fn find_package(&self, path: &Path) -> Option<&Package> {
+        self.members
+            .iter()
+            .filter(|package| path.starts_with(package.dir()))
+            .max_by_key(|package| package.dir().components().count())
+    }
+}
+
+// the kind of the target that `path` belongs to, either the target whose
+// root file it is, or the one whose root directory is the closest ancestor
+// (preferring a library over binaries in the same directory)
+fn find_target_kind(package: &Package, path: &Path) -> &'static str {
+    if let Some(target) = package.targets.iter().find(|t| t.src_path == path) {
+        return target.kind();
+    }
+
+    package
+        .targets
+        .iter()
+        // build scripts sit at the package root, they only own themselves
+        .filter(|target| target.kind() != "build")
+        .filter_map(|target| {
+            let dir = target.src_path.parent()?;
+            path.starts_with(dir)
+                .then(|| (dir.components().count(), target.kind() == "lib", target))
+        })
+        .max_by_key(|(depth, is_lib, _)| (*depth, *is_lib))
+        .map(|(_, _, target)| target.kind())
+        .unwrap_or("other")
+}
+
+#[derive(Default, Serialize)]
+pub struct CrateCounts {
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub targets: BTreeMap<&'static str, Counts>, // by target kind
+}
+
+// keyed by package name and version, files outside of any member package
+// are counted under "other"
+pub fn make_crate_counts(
+    output: &OutputCounts,
+    workspace: &Workspace,
+    config: &Config,
+) -> BTreeMap<String, CrateCounts> {
+    // cargo reports canonical paths
+    let canonical_root = config
+        .abs_root
+        .canonicalize()
+        .unwrap_or_else(|_| config.abs_root.clone());
+
+    let mut crates = BTreeMap::<String, CrateCounts>::new();
+
+    for file in &output.files {
+        let path = match file.path.strip_prefix(&config.abs_root) {
+            Ok(rel_path) => canonical_root.join(rel_path),
+            Err(_) => file.path.clone(),
+        };
+
+        let (name, kind) = match workspace.find_package(&path) {
+            Some(package) => (
+                format!("{} {}", package.name, package.version),
+                find_target_kind(package, &path),
+            ),
+            None => ("other".to_string(), "other"),
+        };
+
+        let crate_counts = crates.entry(name).or_default();
+        crate_counts.counts.merge(&file.counts);
+        crate_counts
+            .targets
+            .entry(kind)
+            .or_default()
+            .merge(&file.counts);
+    }
+
+    crates
+}
+
+// reverse order by number of code lines, forward order by name
+pub fn sort_crates(crates: &BTreeMap<String, CrateCounts>) -> Vec<(&String, &CrateCounts)> {
+    let mut sorted_crates = crates.iter().collect::<Vec<_>>();
+    sorted_crates.sort_by_key(|(_, crate_counts)| Reverse(crate_counts.counts.code));
+    sorted_crates
+}
+
+impl CrateCounts {
+    // reverse order by number of code lines, forward order by kind
+    pub fn sorted_targets(&self) -> Vec<(&'static str, &Counts)> {
+        let mut sorted_targets = self
+            .targets
+            .iter()
+            .map(|(kind, counts)| (*kind, counts))
+            .collect::<Vec<_>>();
+        sorted_targets.sort_by_key(|(_, counts)| Reverse(counts.code));
+        sorted_targets
+    }
+}
+
+// === Dependencies ===
+
+impl NodeDepKind {
+    fn kind(&self) -> DepKind {
+        match self.kind.as_deref() {
+            Some("dev") => DepKind::Dev,
+            Some("build") => DepKind::Build,
+            _ => DepKind::Normal,
+        }
+    }
+}
+
+// the kinds of dependency through which each package is reachable from `roots`,
+// a package keeps the kind of the root's direct dependency it is reached through
+// unless a build dependency is on the way, dev dependencies are only followed from roots
+fn find_dep_kinds<'a>(
+    resolve: &'a Resolve,
+    roots: &[&'a str],
+) -> HashMap<&'a str, BTreeSet<DepKind>> {
+    let nodes = resolve
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node))
+        .collect::<HashMap<_, _>>();
+
+    let mut dep_kinds = HashMap::<&str, BTreeSet<DepKind>>::new();
+    let mut queue = VecDeque::new();
+
+    let edges = |id: &'a str, parent_kind: Option<DepKind>| {
+        nodes
+            .get(id)
+            .into_iter()
+            .flat_map(|node| &node.deps)
+            .flat_map(move |dep| {
+                dep.dep_kinds
+                    .iter()
+                    .filter_map(move |dep_kind| match (dep_kind.kind(), parent_kind) {
+                        (kind, None) => Some(kind),
+                        (DepKind::Dev, Some(_)) => None,
+                        (DepKind::Build, Some(_)) => Some(DepKind::Build),
+                        (DepKind::Normal, Some(kind)) => Some(kind),
+                    })
+                    .map(move |kind| (dep.pkg.as_str(), kind))
+            })
+    };
+
+    for root in roots {
+        queue.extend(edges(root, None));
+    }
+    while let Some((id, kind)) = queue.pop_front() {
+        if dep_kinds.entry(id).or_default().insert(kind) {
+            queue.extend(edges(id, Some(kind)));
+        }
+    }
+
+    dep_kinds
+}
+
+#[derive(Serialize)]
+pub struct DepCounts {
+    pub name: String,
+    pub version: String,
+    pub kinds: BTreeSet<DepKind>,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+// count the sources of the dependencies of the package at `Config::abs_root` (or of the
+// whole workspace at its root) that are of one of the requested `kinds`
+pub fn run_deps_count(config: &Config, kinds: &[DepKind]) -> Result<OutputCounts, AppError> {
+    let Metadata {
+        packages,
+        workspace_members,
+        resolve,
+        ..
+    } = metadata(&config.abs_root, false)?;
+    let resolve = resolve.ok_or_else(|| CargoError::Metadata("missing resolve".to_string()))?;
+
+    let canonical_root = config
+        .abs_root
+        .canonicalize()
+        .unwrap_or_else(|_| config.abs_root.clone());
+    let members = packages
+        .iter()
+        .filter(|package| workspace_members.contains(&package.id))
+        .collect::<Vec<_>>();
+    let mut roots = members
+        .iter()
+        .filter(|package| package.dir() == canonical_root)
+        .map(|package| package.id.as_str())
+        .collect::<Vec<_>>();
+    if roots.is_empty() {
+        roots = members.iter().map(|package| package.id.as_str()).collect();
+    }
+
+    let dep_kinds = find_dep_kinds(&resolve, &roots);
+
+    let mut output = OutputCounts::default();
+    for package in &packages {
+        if workspace_members.contains(&package.id) {
+            continue;
+        }
+        let Some(package_kinds) = dep_kinds.get(package.id.as_str()) else {
+            continue;
+        };
+        if !package_kinds.iter().any(|kind| kinds.contains(kind)) {
+            continue;
+        }
+
+        let dep_output = run_count_in(config, package.dir())?;
+
+        let mut counts = Counts::default();
+        for lang_counts in dep_output.counts.values() {
+            counts.merge(lang_counts);
+        }
+        output.merge(dep_output);
+        output.deps.push(DepCounts {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            kinds: package_kinds.clone(),
+            counts,
+        });
+    }
+
+    Ok(output)
+}
+
+// reverse order by number of code lines, forward order by name
+pub fn sort_deps(deps: &[DepCounts]) -> Vec<&DepCounts> {
+    let mut sorted_deps = deps.iter().collect::<Vec<_>>();
+    sorted_deps.sort_by(|dep1, dep2| {
+        dep2.counts
+            .code
+            .cmp(&dep1.counts.code)
+            .then_with(|| dep1.name.cmp(&dep2.name))
+    });
+    sorted_deps
+}
+
+pub fn format_kinds(kinds: &BTreeSet<DepKind>) -> String {
+    kinds
+        .iter()
+        .map(|kind| match kind {
+            DepKind::Normal => "normal",
+            DepKind::Dev => "dev",
+            DepKind::Build => "build",
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
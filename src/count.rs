@@ -1,5 +1,6 @@
 use std::{
-    collections::{HashMap, hash_map::Entry},
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    ffi::OsStr,
     path::{Path, PathBuf},
 };
 
@@ -7,26 +8,37 @@ use futures::StreamExt;
 use rayon::iter::{ParallelBridge, ParallelIterator};
 use split_async::split;
 use std::fs::File as StdFile;
-use std::io::BufRead as _;
 use std::io::BufReader as StdBufReader;
+use std::io::{BufRead, Read as _};
 use tokio::io::BufReader as TokioBufReader;
-use tokio::{fs::File as TokioFile, io::AsyncBufReadExt, runtime::Runtime};
+use tokio::{
+    fs::File as TokioFile,
+    io::{AsyncBufReadExt, AsyncReadExt},
+    runtime::Runtime,
+};
 
 use globset::GlobSet;
+use ignore::{DirEntry, WalkBuilder};
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, warn};
+use serde::Serialize;
 use thiserror::Error;
-use walkdir::{DirEntry, WalkDir};
 
 use crate::{
-    AppError, Mode,
+    AppError, DepKind, Format, MixedPolicy, Mode,
+    authors::AuthorCounts,
+    cargo::{DepCounts, Workspace},
+    generated,
+    git::{self, BlobReader, ChangeTarget, ChangedFiles, GitError, TreeEntry},
+    gitattributes::{Category, GitAttributes, Overrides},
     languages::{Language, LanguageId, Languages},
+    scanner::{Line, LineScanner},
 };
 
 #[derive(Error, Debug)]
 pub enum CountError {
-    #[error("walkdir error")]
-    WalkDir(#[from] walkdir::Error),
+    #[error("walk error")]
+    Walk(#[from] ignore::Error),
 
     #[error("io error in file {path}")]
     Io { path: PathBuf, err: std::io::Error },
@@ -38,181 +50,424 @@ pub struct Config {
     pub languages: Languages,
     pub exclude: GlobSet, // all glob patterns are absolute
     pub ignore_hidden: bool,
+    pub no_ignore: bool,         // don't respect .gitignore and similar files
+    pub no_linguist: bool,       // don't apply linguist overrides from .gitattributes files
+    pub include_generated: bool, // don't detect generated files
+    pub excluded: bool,          // report excluded files in csv or tsv output
+    pub unmatched: bool,
+    pub rev: Option<String>, // count a git revision instead of the working directory
+    pub changed: Option<ChangedFiles>, // only count the files that changed
+    pub with_old: bool,      // also count the old version of the changed files
     pub quiet: bool,
     pub max_depth: Option<usize>,
     pub follow_links: bool,
     pub machine_readable: bool,
+    pub format: Format,
+    pub no_total: bool,
+    pub by_file: bool,
+    pub by_dir: Option<usize>, // maximum depth of the directory tree
+    pub dir_languages: bool,
+    pub workspace: Option<Workspace>, // only loaded in `by_crate` mode
+    pub by_author: bool,
+    pub deps: Option<Vec<DepKind>>, // the kinds of dependencies to count in `deps` mode
     pub mode: Mode,
+    pub mixed: MixedPolicy,
 }
 
-#[derive(Clone)]
+impl Config {
+    // whether per-file counts are needed for the report
+    pub fn keep_files(&self) -> bool {
+        self.by_file || self.by_dir.is_some() || self.workspace.is_some() || self.by_author
+    }
+
+    // whether the files are read from the index instead of the working directory
+    pub fn counts_index(&self) -> bool {
+        matches!(
+            self.changed,
+            Some(ChangedFiles {
+                target: ChangeTarget::Index,
+                ..
+            })
+        )
+    }
+
+    // `rel_path` is relative to `abs_root`
+    fn is_changed(&self, rel_path: &Path) -> bool {
+        self.changed
+            .as_ref()
+            .is_none_or(|changed| changed.paths.contains(rel_path))
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
 pub struct Counts {
     pub files: usize,
     pub code: usize,
+    pub test_code: usize,
     pub comment: usize,
+    pub doc: usize,
+    pub mixed: usize,
     pub blank: usize,
     pub invalid: usize,
 }
 
 impl Counts {
-    fn merge(&mut self, other: &Counts) {
+    pub fn merge(&mut self, other: &Counts) {
         self.files += other.files;
         self.code += other.code;
+        self.test_code += other.test_code;
         self.comment += other.comment;
+        self.doc += other.doc;
+        self.mixed += other.mixed;
         self.blank += other.blank;
         self.invalid += other.invalid;
     }
+
+    fn add_line(&mut self, line: Line, is_test: bool, mixed: MixedPolicy) {
+        let code = if is_test {
+            &mut self.test_code
+        } else {
+            &mut self.code
+        };
+
+        if line.code && (line.comment || line.doc) {
+            match mixed {
+                MixedPolicy::Code => *code += 1,
+                MixedPolicy::Both if line.doc => {
+                    *code += 1;
+                    self.doc += 1;
+                }
+                MixedPolicy::Both => {
+                    *code += 1;
+                    self.comment += 1;
+                }
+                MixedPolicy::Separate => self.mixed += 1,
+            }
+        } else if line.code {
+            *code += 1;
+        } else if line.doc {
+            self.doc += 1;
+        } else if line.comment {
+            self.comment += 1;
+        } else {
+            self.blank += 1;
+        }
+    }
+}
+
+// Tracks items annotated with one of the language's test attributes (e.g. Rust's
+// `#[cfg(test)] mod tests { ... }` or `#[test] fn ...`) by counting braces in the code
+// outside of comments and string literals
+struct TestTracker<'a> {
+    attributes: &'a [String],
+    depth: usize,
+    pending: bool,             // an attribute was seen but its item hasn't started yet
+    header_depth: usize,       // the nesting of () and [] in the pending item's header
+    angle_depth: usize,        // the nesting of <> in the header, may include comparisons
+    last: Option<char>,        // the last non-whitespace character of the header
+    test_depth: Option<usize>, // the depth outside of the current test item
+}
+
+impl<'a> TestTracker<'a> {
+    fn new(attributes: &'a [String]) -> Self {
+        Self {
+            attributes,
+            depth: 0,
+            pending: false,
+            header_depth: 0,
+            angle_depth: 0,
+            last: None,
+            test_depth: None,
+        }
+    }
+
+    // whether any part of the line belongs to a test item
+    fn track(&mut self, code: &str) -> bool {
+        let mut is_test = self.pending || self.test_depth.is_some();
+
+        let mut rest = code;
+        while let Some(c) = rest.chars().next() {
+            if self.test_depth.is_none()
+                && let Some(attribute) = self.attributes.iter().find(|a| rest.starts_with(*a))
+            {
+                self.pending = true;
+                self.header_depth = 0;
+                self.angle_depth = 0;
+                self.last = None;
+                is_test = true;
+                rest = &rest[attribute.len()..];
+                continue;
+            }
+
+            // character literals aren't removed by the scanner since `'` also starts lifetimes
+            if rest.starts_with("'{'") || rest.starts_with("'}'") {
+                rest = &rest[3..];
+                continue;
+            }
+
+            // arrows and comparisons aren't angle brackets
+            if self.pending
+                && ["->", "=>", "<=", ">="]
+                    .iter()
+                    .any(|op| rest.starts_with(op))
+            {
+                rest = &rest[2..];
+                continue;
+            }
+
+            // only the body of the item or the end of its header outside of () and [] end
+            // the header, not e.g. `;` in `[u8; 3]`, `<` and `>` may be comparisons or shifts
+            // so they only tell the braces of a const generic argument (e.g. `Bar<{ 3 }>`)
+            // from the body
+            let in_header = self.pending && self.header_depth > 0;
+            let const_argument = self.pending
+                && (self.last == Some('<') || (self.last == Some(',') && self.angle_depth > 0));
+            match c {
+                '(' | '[' if self.pending => self.header_depth += 1,
+                ')' | ']' if self.pending => {
+                    self.header_depth = self.header_depth.saturating_sub(1)
+                }
+                '<' if self.pending => self.angle_depth += 1,
+                '>' if self.pending => self.angle_depth = self.angle_depth.saturating_sub(1),
+                '{' => {
+                    if self.pending && !in_header && !const_argument {
+                        self.pending = false;
+                        self.test_depth = Some(self.depth);
+                    }
+                    self.depth += 1;
+                }
+                '}' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.test_depth == Some(self.depth) {
+                        self.test_depth = None;
+                    }
+                }
+                // an item without a body, e.g. `#[cfg(test)] use foo;`
+                ';' if !in_header => self.pending = false,
+                _ => {}
+            }
+            if self.pending && !c.is_whitespace() {
+                self.last = Some(c);
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+
+        is_test
+    }
 }
 
-fn sync_count(path: &Path, lang: &Language) -> Result<Counts, std::io::Error> {
-    let mut code = 0;
-    let mut comment = 0;
-    let mut blank = 0;
-    let mut invalid = 0;
+// whether any of `dirs` (the directories between the root and the file) is one of
+// the language's test directories
+pub fn is_test_file<'a>(dirs: impl IntoIterator<Item = &'a OsStr>, lang: &Language) -> bool {
+    let Some(test_dirs) = &lang.test_dirs else {
+        return false;
+    };
+    dirs.into_iter()
+        .any(|dir| test_dirs.iter().any(|test_dir| dir == test_dir.as_str()))
+}
 
-    let line_comments = lang
-        .line_comments
-        .as_ref()
-        .map(|c| c.as_ref())
-        .unwrap_or(&[]);
-    let block_comments = lang
-        .block_comments
-        .as_ref()
-        .map(|c| c.as_ref())
-        .unwrap_or(&[]);
+// the directories between the root of the walk and the entry
+fn entry_dirs(entry: &DirEntry) -> impl Iterator<Item = &OsStr> {
+    entry
+        .path()
+        .components()
+        .rev()
+        .skip(1)
+        .take(entry.depth().saturating_sub(1))
+        .map(|dir| dir.as_os_str())
+}
+
+fn sync_count(
+    path: &Path,
+    lang: &Language,
+    is_test_file: bool,
+    config: &Config,
+) -> Result<Counts, std::io::Error> {
+    let reader = StdBufReader::new(StdFile::open(path)?);
+    Ok(count_lines(reader, lang, is_test_file, config))
+}
 
-    let mut in_block_comment = None;
-    for line in StdBufReader::new(StdFile::open(path)?).lines() {
+// the scanning core, shared by files on disk and blobs read from git, `f` is called with
+// the index of each line, its kind, and whether it's test code, or `None` if it's invalid
+fn scan_lines(
+    reader: impl BufRead,
+    lang: &Language,
+    is_test_file: bool,
+    mut f: impl FnMut(usize, Option<(Line, bool)>),
+) {
+    let mut scanner = LineScanner::new(lang);
+    let mut tracker = lang.test_attributes.as_deref().map(TestTracker::new);
+    for (i, line) in reader.lines().enumerate() {
         let line = match line {
             Ok(l) => l,
             Err(_err) => {
-                invalid += 1;
+                f(i, None);
                 continue;
             }
         };
-        let line = line.trim();
+        let line = scanner.scan(&line);
+        let is_test = match &mut tracker {
+            Some(tracker) => tracker.track(scanner.code()),
+            None => false,
+        };
+        f(i, Some((line, is_test_file || is_test)));
+    }
+}
 
-        if line.is_empty() {
-            blank += 1;
-            continue;
-        }
+fn count_lines(
+    reader: impl BufRead,
+    lang: &Language,
+    is_test_file: bool,
+    config: &Config,
+) -> Counts {
+    let mut counts = Counts {
+        files: 1,
+        ..Counts::default()
+    };
 
-        if let Some(end_token) = in_block_comment {
-            comment += 1;
-            if line.ends_with(end_token) {
-                in_block_comment = None;
-            }
-            continue;
-        }
+    scan_lines(reader, lang, is_test_file, |_, line| match line {
+        Some((line, is_test)) => counts.add_line(line, is_test, config.mixed),
+        None => counts.invalid += 1,
+    });
+
+    counts
+}
 
-        if line_comments.iter().any(|lc| line.starts_with(lc)) {
-            comment += 1;
-            continue;
+// the counts of a file without a language, every non-blank line is code
+fn count_plain_lines(text: &str) -> Counts {
+    let mut counts = Counts {
+        files: 1,
+        ..Counts::default()
+    };
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            counts.blank += 1;
+        } else {
+            counts.code += 1;
         }
+    }
+    counts
+}
 
-        if let Some((_, end_token)) = block_comments
-            .iter()
-            .find(|(start_token, _)| line.starts_with(start_token))
-        {
-            if !line.ends_with(end_token) {
-                in_block_comment = Some(end_token);
-            }
-            comment += 1;
-            continue;
+// like `count_lines`, but each line is counted for its owner, `owners[i]` owns the line
+// `i`, the file is counted once for every owner
+pub fn count_lines_by_owner<'a>(
+    reader: impl BufRead,
+    lang: &Language,
+    is_test_file: bool,
+    config: &Config,
+    owners: &[&'a str],
+) -> HashMap<&'a str, Counts> {
+    let mut counts = HashMap::<&str, Counts>::new();
+
+    scan_lines(reader, lang, is_test_file, |i, line| {
+        let Some(owner) = owners.get(i) else {
+            return;
+        };
+        let counts = counts.entry(owner).or_insert_with(|| Counts {
+            files: 1,
+            ..Counts::default()
+        });
+        match line {
+            Some((line, is_test)) => counts.add_line(line, is_test, config.mixed),
+            None => counts.invalid += 1,
         }
+    });
 
-        code += 1;
-    }
+    counts
+}
 
-    Ok(Counts {
+async fn async_count(
+    path: &Path,
+    lang: &Language,
+    is_test_file: bool,
+    config: &Config,
+) -> Result<Counts, std::io::Error> {
+    let mut counts = Counts {
         files: 1,
-        code,
-        comment,
-        blank,
-        invalid,
-    })
-}
+        ..Counts::default()
+    };
 
-async fn async_count(path: &Path, lang: &Language) -> Result<Counts, std::io::Error> {
-    let mut code = 0;
-    let mut comment = 0;
-    let mut blank = 0;
-    let mut invalid = 0;
-
-    let line_comments = lang
-        .line_comments
-        .as_ref()
-        .map(|c| c.as_ref())
-        .unwrap_or(&[]);
-    let block_comments = lang
-        .block_comments
-        .as_ref()
-        .map(|c| c.as_ref())
-        .unwrap_or(&[]);
-
-    let mut in_block_comment = None;
+    let mut scanner = LineScanner::new(lang);
+    let mut tracker = lang.test_attributes.as_deref().map(TestTracker::new);
     let mut iter = TokioBufReader::new(TokioFile::open(path).await?).lines();
     loop {
         let line = match iter.next_line().await {
             Ok(l) => l,
             Err(_err) => {
-                invalid += 1;
+                counts.invalid += 1;
                 continue;
             }
         };
         let Some(line) = line else { break };
-        let line = line.trim();
-
-        if line.is_empty() {
-            blank += 1;
-            continue;
-        }
+        let line = scanner.scan(&line);
+        let is_test = match &mut tracker {
+            Some(tracker) => tracker.track(scanner.code()),
+            None => false,
+        };
+        counts.add_line(line, is_test_file || is_test, config.mixed);
+    }
 
-        if let Some(end_token) = in_block_comment {
-            comment += 1;
-            if line.ends_with(end_token) {
-                in_block_comment = None;
-            }
-            continue;
-        }
+    Ok(counts)
+}
 
-        if line_comments.iter().any(|lc| line.starts_with(lc)) {
-            comment += 1;
-            continue;
-        }
+enum EntryResult {
+    Some {
+        path: PathBuf,
+        lang_id: LanguageId,
+        category: Option<Category>,
+        counts: Counts,
+    },
+    // a file without a language that is still excluded, like a lockfile
+    Excluded {
+        category: Category,
+        counts: Counts,
+    },
+    Unmatched(Option<UnmatchedFile>), // only `Some` if `Config::unmatched`
+    Skipped,                          // dir, symlink, or unchanged file
+    Err(CountError),
+}
 
-        if let Some((_, end_token)) = block_comments
-            .iter()
-            .find(|(start_token, _)| line.starts_with(start_token))
-        {
-            if !line.ends_with(end_token) {
-                in_block_comment = Some(end_token);
-            }
-            comment += 1;
-            continue;
-        }
+#[derive(Clone)]
+struct UnmatchedFile {
+    key: String, // the extension, or the file name if there is none
+    bytes: u64,
+}
 
-        code += 1;
+impl UnmatchedFile {
+    fn new(path: &Path, bytes: u64) -> Self {
+        let key = match path.extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy()),
+            None => path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        Self { key, bytes }
     }
+}
 
-    Ok(Counts {
-        files: 1,
-        code,
-        comment,
-        blank,
-        invalid,
-    })
+#[derive(Default, Serialize)]
+pub struct UnmatchedCounts {
+    pub files: usize,
+    pub bytes: u64,
 }
 
-enum EntryResult {
-    Some { lang_id: LanguageId, counts: Counts },
-    None, // file didn't match
-    Err(CountError),
+pub struct FileCounts {
+    pub path: PathBuf,
+    pub lang_id: LanguageId,
+    pub counts: Counts,
 }
 
 #[derive(Default)]
 pub struct OutputCounts {
     pub counts: HashMap<LanguageId, Counts>,
+    pub files: Vec<FileCounts>,     // only filled if `Config::keep_files`
+    pub deps: Vec<DepCounts>,       // only filled in `deps` mode
+    pub authors: Vec<AuthorCounts>, // only filled in `by_author` mode, after counting
+    pub excluded: BTreeMap<Category, Counts>, // not included in `counts` or `files`
+    pub unmatched: HashMap<String, UnmatchedCounts>, // only filled if `Config::unmatched`
     pub unmatched_files: usize,
     pub error_files: usize,
 }
@@ -229,102 +484,307 @@ impl OutputCounts {
         }
     }
 
-    fn merge(&mut self, other: &Self) {
+    fn append_result(&mut self, entry_result: EntryResult, config: &Config) {
+        match entry_result {
+            EntryResult::Some {
+                category: Some(category),
+                counts,
+                ..
+            }
+            | EntryResult::Excluded { category, counts } => {
+                self.excluded.entry(category).or_default().merge(&counts);
+            }
+            EntryResult::Some {
+                path,
+                lang_id,
+                category: None,
+                counts,
+            } => {
+                self.append_counts(lang_id, &counts);
+                if config.keep_files() {
+                    self.files.push(FileCounts {
+                        path,
+                        lang_id,
+                        counts,
+                    });
+                }
+            }
+            EntryResult::Unmatched(file) => {
+                self.unmatched_files += 1;
+                if let Some(UnmatchedFile { key, bytes }) = file {
+                    let unmatched = self.unmatched.entry(key).or_default();
+                    unmatched.files += 1;
+                    unmatched.bytes += bytes;
+                }
+            }
+            EntryResult::Skipped => {}
+            EntryResult::Err(_err) => self.error_files += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: Self) {
         for (lang_id, counts) in &other.counts {
             self.append_counts(*lang_id, counts);
         }
+        self.files.extend(other.files);
+        self.deps.extend(other.deps);
+        self.authors.extend(other.authors);
+        for (category, counts) in &other.excluded {
+            self.excluded.entry(*category).or_default().merge(counts);
+        }
+        for (key, counts) in other.unmatched {
+            let unmatched = self.unmatched.entry(key).or_default();
+            unmatched.files += counts.files;
+            unmatched.bytes += counts.bytes;
+        }
         self.unmatched_files += other.unmatched_files;
         self.error_files += other.error_files;
     }
 }
 
+// display path relative to cwd
+// default to absolute path if `stip_prefix` fails
+pub fn display_path(path: &Path, config: &Config) -> String {
+    path.strip_prefix(&config.abs_root)
+        .map(|rel_path| config.rel_root.join(rel_path).to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string_lossy().to_string())
+}
+
 // === Walk internals ===
 
-fn make_walk_iter(config: &Config) -> impl Iterator<Item = Result<DirEntry, walkdir::Error>> {
-    let mut iter = WalkDir::new(&config.abs_root);
-    if let Some(max_depth) = config.max_depth {
-        iter = iter.max_depth(max_depth);
+fn make_walk_iter(
+    config: &Config,
+    root: &Path,
+) -> impl Iterator<Item = Result<DirEntry, ignore::Error>> {
+    let exclude = config.exclude.clone();
+    let no_ignore = config.no_ignore;
+
+    // `standard_filters` covers .gitignore, .ignore, .git/info/exclude, and core.excludesFile,
+    // git's own directory is skipped separately since it's only hidden
+    WalkBuilder::new(root)
+        .standard_filters(!config.no_ignore)
+        .require_git(false)
+        .hidden(config.ignore_hidden)
+        .max_depth(config.max_depth)
+        .follow_links(config.follow_links)
+        .filter_entry(move |entry| {
+            !exclude.is_match(entry.path()) && (no_ignore || !is_git_dir(entry))
+        })
+        .build()
+}
+
+fn is_git_dir(entry: &DirEntry) -> bool {
+    entry.file_name() == ".git" && entry.file_type().is_some_and(|ft| ft.is_dir())
+}
+
+// shebangs longer than this are ignored, this avoids reading all of a large binary file
+const MAX_SHEBANG_LEN: u64 = 256;
+
+// heuristics only look at the beginning of a file
+const HEURISTIC_LINES: usize = 50;
+const MAX_HEURISTIC_LEN: u64 = 16 * 1024;
+
+// up to `max_len` bytes from the start of the file, `None` if it can't be read
+fn sync_read_head(path: &Path, max_len: u64) -> Option<String> {
+    let mut head = Vec::new();
+    StdFile::open(path)
+        .ok()?
+        .take(max_len)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+async fn async_read_head(path: &Path, max_len: u64) -> Option<String> {
+    let mut head = Vec::new();
+    TokioFile::open(path)
+        .await
+        .ok()?
+        .take(max_len)
+        .read_to_end(&mut head)
+        .await
+        .ok()?;
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+// how the language of a file is found, some cases need the beginning of the file
+enum Detection<'a> {
+    Done(Option<LanguageId>),
+    Shebang,
+    Heuristics(&'a [LanguageId]), // the candidates
+}
+
+// the language of a file from its name, or from its `linguist-language` attribute
+fn detect<'a>(path: &Path, overrides: &Overrides, config: &'a Config) -> Detection<'a> {
+    let overridden = overrides
+        .language
+        .as_deref()
+        .and_then(|name| config.languages.find_by_name(name));
+    let file_name = path.file_name().unwrap_or_default();
+
+    match (overridden, config.languages.find(file_name)) {
+        (Some(lang_id), _) => Detection::Done(Some(lang_id)),
+        (None, []) if path.extension().is_none() => Detection::Shebang,
+        (None, []) => Detection::Done(None),
+        (None, [lang_id]) => Detection::Done(Some(*lang_id)),
+        // the file is only read if its extension is ambiguous
+        (None, candidates) => Detection::Heuristics(candidates),
     }
-    if config.follow_links {
-        iter = iter.follow_links(true);
+}
+
+impl Detection<'_> {
+    // how much of the beginning of the file is needed to finish
+    fn head_len(&self) -> Option<u64> {
+        match self {
+            Detection::Done(_) => None,
+            Detection::Shebang => Some(MAX_SHEBANG_LEN),
+            Detection::Heuristics(_) => Some(MAX_HEURISTIC_LEN),
+        }
     }
-    let iter = iter.into_iter().filter_entry(|entry| {
-        // `as_encoded_bytes` returns a "self-synchronizing superset of UTF-8"
-        if config.ignore_hidden && entry.file_name().as_encoded_bytes().starts_with(&[b'.']) {
-            return false;
+
+    // `head` is `None` if the file couldn't be read
+    fn finish(self, head: Option<&str>, config: &Config) -> Option<LanguageId> {
+        match self {
+            Detection::Done(lang_id) => lang_id,
+            Detection::Shebang => config.languages.find_by_shebang(first_lines(head?, 1)),
+            Detection::Heuristics(candidates) => Some(
+                config
+                    .languages
+                    .disambiguate(candidates, first_lines(head.unwrap_or(""), HEURISTIC_LINES)),
+            ),
         }
-        !config.exclude.is_match(entry.path())
-    });
+    }
+}
+
+// generated file markers and minification are looked for at the beginning of a file
+const MAX_GENERATED_HEAD_LEN: u64 = 4 * 1024;
+
+// linguist overrides take precedence over the detection of generated files
+fn detect_generated(overrides: &Overrides, config: &Config) -> bool {
+    !config.include_generated && overrides.generated != Some(false)
+}
+
+// whether a file without a language is still excluded as generated, lockfiles mostly
+// don't match any language but are known by their names
+fn is_generated_name(path: &Path, overrides: &Overrides, config: &Config) -> bool {
+    detect_generated(overrides, config)
+        && path.file_name().is_some_and(generated::matches_file_name)
+}
+
+#[split]
+async fn is_generated(path: &Path) -> bool {
+    if path.file_name().is_some_and(generated::matches_file_name) {
+        return true;
+    }
 
-    iter
+    match choose!(read_head)(path, MAX_GENERATED_HEAD_LEN).await {
+        Some(head) => generated::matches_head(path.extension(), &head),
+        None => false,
+    }
+}
+
+// the first `n` lines of `text`
+fn first_lines(text: &str, n: usize) -> &str {
+    match text.match_indices('\n').nth(n.saturating_sub(1)) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
 }
 
 #[split]
 async fn walk_loop_body(
-    entry: Result<DirEntry, walkdir::Error>,
+    entry: Result<DirEntry, ignore::Error>,
     config: &Config,
     pbar: Option<&ProgressBar>,
+    gitattributes: Option<&GitAttributes>,
 ) -> EntryResult {
     let entry = match entry {
-        Ok(e) if e.file_type().is_file() => e,
-        Ok(_) => return EntryResult::None, // dir or symlink
+        Ok(e) if e.file_type().is_some_and(|ft| ft.is_file()) => e,
+        Ok(_) => return EntryResult::Skipped,
         Err(err) => return EntryResult::Err(err.into()),
     };
 
+    let rel_path = entry
+        .path()
+        .strip_prefix(&config.abs_root)
+        .unwrap_or(entry.path());
+    if !config.is_changed(rel_path) {
+        return EntryResult::Skipped;
+    }
+
     info!("{:?}", entry.path());
     pbar.map(|pbar| {
         pbar.inc(1);
+        pbar.set_message(display_path(entry.path(), config));
+    });
 
-        // display path relative to cwd
-        // default to absolute path if `stip_prefix` fails
-        let display_path = entry
-            .path()
-            .strip_prefix(&config.abs_root)
-            .map(|rel_path| config.rel_root.join(rel_path).to_string_lossy().to_string())
-            .unwrap_or_else(|_| entry.path().to_string_lossy().to_string());
+    let overrides = gitattributes
+        .map(|gitattributes| gitattributes.overrides(entry.path()))
+        .unwrap_or_default();
 
-        pbar.set_message(display_path);
-    });
+    let detection = detect(entry.path(), &overrides, config);
+    let head = match detection.head_len() {
+        Some(len) => choose!(read_head)(entry.path(), len).await,
+        None => None,
+    };
+    let Some(lang_id) = detection.finish(head.as_deref(), config) else {
+        let text = match is_generated_name(entry.path(), &overrides, config) {
+            true => choose!(read_head)(entry.path(), u64::MAX).await,
+            false => None,
+        };
+        if let Some(text) = text {
+            return EntryResult::Excluded {
+                category: overrides.category().unwrap_or(Category::Generated),
+                counts: count_plain_lines(&text),
+            };
+        }
+        let file = config.unmatched.then(|| {
+            let bytes = entry.metadata().map_or(0, |metadata| metadata.len());
+            UnmatchedFile::new(entry.path(), bytes)
+        });
+        return EntryResult::Unmatched(file);
+    };
 
-    for (lang_id, lang) in (&config.languages).into_iter().enumerate() {
-        for ext in &lang.extensions {
-            // `as_encoded_bytes` returns a "self-synchronizing superset of UTF-8"
-            // This means that if the last few bytes match the ASCII values for a file extension,
-            // then we can safely assume that's what they are
-            if entry
-                .file_name()
-                .as_encoded_bytes()
-                .ends_with(ext.as_bytes())
-            {
-                let counts: Result<_, _> = choose!(count)(entry.path(), lang).await;
-                return match counts {
-                    Ok(counts) => EntryResult::Some { lang_id, counts },
-                    Err(err) => {
-                        warn!("error in file {:?}", entry.path());
-                        EntryResult::Err(CountError::Io {
-                            path: entry.into_path(),
-                            err,
-                        })
-                    }
-                };
-            }
+    let category = match overrides.category() {
+        Some(category) => Some(category),
+        None if !detect_generated(&overrides, config) => None,
+        None => choose!(is_generated)(entry.path())
+            .await
+            .then_some(Category::Generated),
+    };
+
+    let lang = &config.languages[lang_id];
+    let is_test_file = is_test_file(entry_dirs(&entry), lang);
+    let counts: Result<_, _> = choose!(count)(entry.path(), lang, is_test_file, config).await;
+    match counts {
+        Ok(counts) => EntryResult::Some {
+            path: entry.into_path(),
+            lang_id,
+            category,
+            counts,
+        },
+        Err(err) => {
+            warn!("error in file {:?}", entry.path());
+            EntryResult::Err(CountError::Io {
+                path: entry.into_path(),
+                err,
+            })
         }
     }
-
-    EntryResult::None
 }
 
-fn sync_walk(config: &Config, pbar: Option<&ProgressBar>) -> Result<OutputCounts, CountError> {
-    let iter = make_walk_iter(config);
+fn sync_walk(
+    config: &Config,
+    root: &Path,
+    pbar: Option<&ProgressBar>,
+    gitattributes: Option<&GitAttributes>,
+) -> Result<OutputCounts, CountError> {
+    let iter = make_walk_iter(config, root);
 
     let output = iter
-        .map(|entry| sync_walk_loop_body(entry, config, pbar))
+        .map(|entry| sync_walk_loop_body(entry, config, pbar, gitattributes))
         .fold(OutputCounts::default(), |mut output, entry_result| {
-            match entry_result {
-                EntryResult::Some { lang_id, counts } => output.append_counts(lang_id, &counts),
-                EntryResult::None => output.unmatched_files += 1,
-                EntryResult::Err(_err) => output.error_files += 1,
-            }
+            output.append_result(entry_result, config);
             output
         });
 
@@ -333,19 +793,17 @@ fn sync_walk(config: &Config, pbar: Option<&ProgressBar>) -> Result<OutputCounts
 
 async fn async_walk(
     config: &Config,
+    root: &Path,
     pbar: Option<&ProgressBar>,
+    gitattributes: Option<&GitAttributes>,
 ) -> Result<OutputCounts, CountError> {
-    let iter = make_walk_iter(config);
+    let iter = make_walk_iter(config, root);
 
     let output = futures::stream::iter(iter)
-        .map(|entry| async_walk_loop_body(entry, config, pbar))
+        .map(|entry| async_walk_loop_body(entry, config, pbar, gitattributes))
         .buffer_unordered(20)
         .fold(OutputCounts::default(), async |mut output, entry_result| {
-            match entry_result {
-                EntryResult::Some { lang_id, counts } => output.append_counts(lang_id, &counts),
-                EntryResult::None => output.unmatched_files += 1,
-                EntryResult::Err(_err) => output.error_files += 1,
-            }
+            output.append_result(entry_result, config);
             output
         })
         .await;
@@ -353,27 +811,28 @@ async fn async_walk(
     Ok(output)
 }
 
-fn parallel_walk(config: &Config, pbar: Option<&ProgressBar>) -> Result<OutputCounts, CountError> {
-    let iter = make_walk_iter(config);
+fn parallel_walk(
+    config: &Config,
+    root: &Path,
+    pbar: Option<&ProgressBar>,
+    gitattributes: Option<&GitAttributes>,
+) -> Result<OutputCounts, CountError> {
+    let iter = make_walk_iter(config, root);
 
     let output = iter
         .par_bridge()
-        .map(|entry| sync_walk_loop_body(entry, config, pbar))
+        .map(|entry| sync_walk_loop_body(entry, config, pbar, gitattributes))
         .fold(
             || OutputCounts::default(),
             |mut output, entry_result| {
-                match entry_result {
-                    EntryResult::Some { lang_id, counts } => output.append_counts(lang_id, &counts),
-                    EntryResult::None => output.unmatched_files += 1,
-                    EntryResult::Err(_err) => output.error_files += 1,
-                }
+                output.append_result(entry_result, config);
                 output
             },
         )
         .reduce(
             || OutputCounts::default(),
             |mut output1, output2| {
-                output1.merge(&output2);
+                output1.merge(output2);
                 output1
             },
         );
@@ -382,26 +841,381 @@ fn parallel_walk(config: &Config, pbar: Option<&ProgressBar>) -> Result<OutputCo
 }
 
 pub fn run_count(config: &Config) -> Result<OutputCounts, AppError> {
-    let rt = Runtime::new()?;
+    run_count_in(config, &config.abs_root)
+}
 
-    let pbar = (!config.quiet).then(|| {
+// `None` with --quiet
+pub fn make_pbar(config: &Config) -> Option<ProgressBar> {
+    (!config.quiet).then(|| {
         let pbar = ProgressBar::no_length();
         pbar.set_style(
             ProgressStyle::with_template("[{elapsed_precise}] {human_pos} {msg}").unwrap(),
         );
         pbar
-    });
+    })
+}
+
+// count a directory other than `Config::abs_root`
+pub fn run_count_in(config: &Config, root: &Path) -> Result<OutputCounts, AppError> {
+    let rt = Runtime::new()?;
+
+    let pbar = make_pbar(config);
+
+    let gitattributes = (!config.no_linguist).then(|| GitAttributes::new(root));
 
     let output = match config.mode {
-        Mode::Sync => sync_walk(config, pbar.as_ref()),
+        Mode::Sync => sync_walk(config, root, pbar.as_ref(), gitattributes.as_ref()),
         Mode::Async => {
-            let async_output = async_walk(config, pbar.as_ref());
+            let async_output = async_walk(config, root, pbar.as_ref(), gitattributes.as_ref());
             rt.block_on(async_output)
         }
-        Mode::Parallel => parallel_walk(config, pbar.as_ref()),
+        Mode::Parallel => parallel_walk(config, root, pbar.as_ref(), gitattributes.as_ref()),
     };
 
     pbar.as_ref().map(|pbar| pbar.finish_and_clear());
 
     Ok(output?)
 }
+
+// === Git revisions ===
+
+// whether a file of the tree passes the same filters as the walk of the working directory,
+// except for ignore files since the tree only contains tracked files
+fn keep_tree_entry(entry: &TreeEntry, config: &Config) -> bool {
+    let hidden = entry
+        .path
+        .iter()
+        .any(|component| component.as_encoded_bytes().starts_with(b"."));
+    let depth = entry.path.components().count();
+    // the walk prunes excluded directories
+    let excluded = entry
+        .path
+        .ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .any(|path| config.exclude.is_match(config.abs_root.join(path)));
+
+    !(config.ignore_hidden && hidden)
+        && config.max_depth.is_none_or(|max_depth| depth <= max_depth)
+        && !excluded
+        && config.is_changed(&entry.path)
+}
+
+// the counterpart of `walk_loop_body` for a file read from git, the blob is only read
+// if it's needed
+fn count_blob(
+    entry: &TreeEntry,
+    blobs: &mut BlobReader,
+    config: &Config,
+    gitattributes: Option<&GitAttributes>,
+) -> Result<EntryResult, GitError> {
+    let path = config.abs_root.join(&entry.path);
+    let overrides = gitattributes
+        .map(|gitattributes| gitattributes.overrides(&path))
+        .unwrap_or_default();
+
+    let mut contents = None;
+    let mut read = |contents: &mut Option<Vec<u8>>| -> Result<(), GitError> {
+        if contents.is_none() {
+            *contents = Some(blobs.read(&entry.oid)?);
+        }
+        Ok(())
+    };
+    let head = |contents: &Option<Vec<u8>>, len: u64| {
+        let contents = contents.as_deref().unwrap_or_default();
+        let len = contents.len().min(len as usize);
+        String::from_utf8_lossy(&contents[..len]).into_owned()
+    };
+
+    let detection = detect(&path, &overrides, config);
+    let detection_head = match detection.head_len() {
+        Some(len) => {
+            read(&mut contents)?;
+            Some(head(&contents, len))
+        }
+        None => None,
+    };
+    let Some(lang_id) = detection.finish(detection_head.as_deref(), config) else {
+        if is_generated_name(&path, &overrides, config) {
+            read(&mut contents)?;
+            let contents = contents.unwrap_or_default();
+            return Ok(EntryResult::Excluded {
+                category: overrides.category().unwrap_or(Category::Generated),
+                counts: count_plain_lines(&String::from_utf8_lossy(&contents)),
+            });
+        }
+        let file = config
+            .unmatched
+            .then(|| UnmatchedFile::new(&path, entry.size));
+        return Ok(EntryResult::Unmatched(file));
+    };
+
+    read(&mut contents)?;
+    let category = match overrides.category() {
+        Some(category) => Some(category),
+        None if !detect_generated(&overrides, config) => None,
+        None => {
+            let is_generated = path.file_name().is_some_and(generated::matches_file_name)
+                || generated::matches_head(
+                    path.extension(),
+                    &head(&contents, MAX_GENERATED_HEAD_LEN),
+                );
+            is_generated.then_some(Category::Generated)
+        }
+    };
+
+    let lang = &config.languages[lang_id];
+    let dirs = entry.path.parent().into_iter().flat_map(Path::iter);
+    let is_test_file = is_test_file(dirs, lang);
+    let contents = contents.unwrap_or_default();
+    let counts = count_lines(contents.as_slice(), lang, is_test_file, config);
+
+    Ok(EntryResult::Some {
+        path,
+        lang_id,
+        category,
+        counts,
+    })
+}
+
+// the result of a blob without its path
+#[derive(Clone)]
+enum CachedBlob {
+    Counted {
+        lang_id: LanguageId,
+        category: Option<Category>,
+        counts: Counts,
+    },
+    Excluded {
+        category: Category,
+        counts: Counts,
+    },
+    Unmatched(Option<UnmatchedFile>),
+}
+
+/// The results of the blobs already counted, by path and object id, so that files
+/// that didn't change between revisions are never read or counted again
+#[derive(Default)]
+pub struct BlobCache {
+    gitattributes: Vec<String>, // the object ids of the .gitattributes files used
+    blobs: HashMap<(PathBuf, String), CachedBlob>,
+}
+
+impl BlobCache {
+    fn get(&self, entry: &TreeEntry, config: &Config) -> Option<EntryResult> {
+        let cached = self.blobs.get(&(entry.path.clone(), entry.oid.clone()))?;
+        Some(match cached.clone() {
+            CachedBlob::Counted {
+                lang_id,
+                category,
+                counts,
+            } => EntryResult::Some {
+                path: config.abs_root.join(&entry.path),
+                lang_id,
+                category,
+                counts,
+            },
+            CachedBlob::Excluded { category, counts } => EntryResult::Excluded { category, counts },
+            CachedBlob::Unmatched(file) => EntryResult::Unmatched(file),
+        })
+    }
+
+    fn insert(&mut self, entry: &TreeEntry, entry_result: &EntryResult) {
+        let cached = match entry_result {
+            EntryResult::Some {
+                lang_id,
+                category,
+                counts,
+                ..
+            } => CachedBlob::Counted {
+                lang_id: *lang_id,
+                category: *category,
+                counts: counts.clone(),
+            },
+            EntryResult::Excluded { category, counts } => CachedBlob::Excluded {
+                category: *category,
+                counts: counts.clone(),
+            },
+            EntryResult::Unmatched(file) => CachedBlob::Unmatched(file.clone()),
+            EntryResult::Skipped | EntryResult::Err(_) => return,
+        };
+        self.blobs
+            .insert((entry.path.clone(), entry.oid.clone()), cached);
+    }
+}
+
+// count the files under `Config::abs_root` as they are in `rev`, reading them from the
+// object database instead of the working directory, always single-threaded
+pub fn run_rev_count(config: &Config, rev: &str) -> Result<OutputCounts, AppError> {
+    run_tree_count(config, git::ls_tree(&config.abs_root, rev)?)
+}
+
+// like `run_rev_count`, with the files as they are in the index, only the changed files
+// and the .gitattributes files are listed
+pub fn run_index_count(config: &Config) -> Result<OutputCounts, AppError> {
+    let entries = git::ls_index(&config.abs_root, |path| {
+        config.is_changed(path) || path.file_name() == Some(OsStr::new(".gitattributes"))
+    })?;
+    run_tree_count(config, entries)
+}
+
+fn run_tree_count(config: &Config, entries: Vec<TreeEntry>) -> Result<OutputCounts, AppError> {
+    let mut blobs = BlobReader::new(&config.abs_root)?;
+    let pbar = make_pbar(config);
+
+    let output = count_entries(
+        config,
+        entries,
+        &mut blobs,
+        &mut BlobCache::default(),
+        pbar.as_ref(),
+    );
+
+    if let Some(pbar) = &pbar {
+        pbar.finish_and_clear();
+    }
+
+    output
+}
+
+// blobs are only counted if they aren't in `cache` yet
+pub fn count_rev(
+    config: &Config,
+    rev: &str,
+    blobs: &mut BlobReader,
+    cache: &mut BlobCache,
+    pbar: Option<&ProgressBar>,
+) -> Result<OutputCounts, AppError> {
+    let entries = git::ls_tree(&config.abs_root, rev)?;
+    count_entries(config, entries, blobs, cache, pbar)
+}
+
+// `entries` are all the files of a tree, before filtering
+fn count_entries(
+    config: &Config,
+    entries: Vec<TreeEntry>,
+    blobs: &mut BlobReader,
+    cache: &mut BlobCache,
+    pbar: Option<&ProgressBar>,
+) -> Result<OutputCounts, AppError> {
+    let root = &config.abs_root;
+    let (entries, other_entries) = entries
+        .into_iter()
+        .partition::<Vec<_>, _>(|entry| keep_tree_entry(entry, config));
+
+    // like in the walk, the overrides apply even if the .gitattributes files aren't counted
+    let gitattributes_entries = entries
+        .iter()
+        .chain(&other_entries)
+        .filter(|entry| entry.path.file_name() == Some(OsStr::new(".gitattributes")))
+        .collect::<Vec<_>>();
+    let gitattributes = if config.no_linguist {
+        None
+    } else {
+        // cached results may depend on the previous attributes
+        let oids = gitattributes_entries
+            .iter()
+            .map(|entry| entry.oid.clone())
+            .collect::<Vec<_>>();
+        if oids != cache.gitattributes {
+            cache.blobs.clear();
+            cache.gitattributes = oids;
+        }
+
+        let mut files = Vec::new();
+        for entry in gitattributes_entries {
+            let dir = root.join(entry.path.parent().unwrap_or(Path::new("")));
+            let text = String::from_utf8_lossy(&blobs.read(&entry.oid)?).into_owned();
+            files.push((dir, text));
+        }
+        Some(GitAttributes::from_files(root, files))
+    };
+
+    let mut output = OutputCounts::default();
+    for entry in &entries {
+        info!("{:?}", entry.path);
+        if let Some(pbar) = pbar {
+            pbar.inc(1);
+            pbar.set_message(entry.path.to_string_lossy().into_owned());
+        }
+
+        let entry_result = match cache.get(entry, config) {
+            Some(entry_result) => entry_result,
+            None => {
+                let entry_result = count_blob(entry, blobs, config, gitattributes.as_ref())?;
+                cache.insert(entry, &entry_result);
+                entry_result
+            }
+        };
+        output.append_result(entry_result, config);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // whether each line belongs to a test item
+    fn track(text: &str) -> Vec<bool> {
+        let languages = Languages::builtin();
+        let lang = &languages[languages.find_by_name("Rust").unwrap()];
+        let mut scanner = LineScanner::new(lang);
+        let mut tracker = TestTracker::new(lang.test_attributes.as_deref().unwrap());
+        text.lines()
+            .map(|line| {
+                scanner.scan(line);
+                tracker.track(scanner.code())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_module() {
+        let text = "#[cfg(test)]\nmod tests {\n    fn a() {}\n}\nfn b() {}";
+        assert_eq!(track(text), [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_function() {
+        let text = "#[test]\nfn a() -> Result<(), Box<dyn Error>> {\n    b();\n}\nfn b() {}";
+        assert_eq!(track(text), [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn impl_with_const_generic() {
+        let text = "#[cfg(test)]\nimpl Foo for Bar<{ 3 }> {\n    fn a() {}\n}\nfn b() {}";
+        assert_eq!(track(text), [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn statement_item() {
+        let text = "#[cfg(test)]\nconst X: [u8; 3] = [1, 2, 3];\nfn b() {\n}";
+        assert_eq!(track(text), [true, true, false, false]);
+        let text = "#[cfg(test)] use foo;\nfn b() {}";
+        assert_eq!(track(text), [true, false]);
+    }
+
+    #[test]
+    fn comparison_in_header() {
+        let text = "#[cfg(test)]\nconst BIG: bool = 1 < 2;\nfn main() {\n    a();\n}";
+        assert_eq!(track(text), [true, true, false, false, false]);
+        let text = "#[test]\nfn t() where [(); 1 << 2]: Sized {\n    a();\n}\nfn b() {}";
+        assert_eq!(track(text), [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn const_generic_arguments() {
+        let text = "#[cfg(test)]\nimpl Foo<u8, { 3 }> for Bar {\n    fn a() {}\n}\nfn b() {}";
+        assert_eq!(track(text), [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn nested_braces() {
+        let text = "fn a() {\n    #[test]\n    fn b() {\n        if c { d(); } else {\n            e();\n        }\n    }\n    f();\n}";
+        assert_eq!(
+            track(text),
+            [false, true, true, true, true, true, true, false, false]
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    authors::sort_authors,
+    cargo::{CrateCounts, format_kinds, make_crate_counts, sort_crates, sort_deps},
+    count::{Config, Counts, OutputCounts, display_path},
+    diff::{DiffOutput, FileDiff},
+    history::HistoryOutput,
+    table::{Column, columns, sort_counts, sort_files, sort_unmatched},
+    tree::{DirNode, make_tree},
+    util::{delta, format_date, percent_change},
+};
+
+fn escape(field: &str, separator: char) -> String {
+    if separator == '\t' {
+        // tsv has no quoting, replace anything that would break the row
+        return field.replace(['\t', '\n', '\r'], " ");
+    }
+
+    if field.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn push_row(out: &mut String, fields: impl IntoIterator<Item = String>, separator: char) {
+    let row = fields
+        .into_iter()
+        .map(|field| escape(&field, separator))
+        .collect::<Vec<_>>();
+    out.push_str(&row.join(&separator.to_string()));
+    out.push('\n');
+}
+
+// one section per requested report, in the same order as the tables, separated by an
+// empty line, the per-language counts always come first
+pub fn make_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    let mut sections = vec![make_language_csv(output, config, separator)];
+
+    if config.excluded {
+        sections.push(make_excluded_csv(output, config, separator));
+    }
+    if config.by_file {
+        sections.push(make_file_csv(output, config, separator));
+    }
+    if let Some(max_depth) = config.by_dir {
+        let tree = make_tree(output, config, max_depth);
+        sections.push(make_dir_csv(&tree, config, separator));
+    }
+    if let Some(workspace) = &config.workspace {
+        let crates = make_crate_counts(output, workspace, config);
+        sections.push(make_crate_csv(&crates, config, separator));
+    }
+    if config.deps.is_some() {
+        sections.push(make_dep_csv(output, config, separator));
+    }
+    if config.unmatched {
+        sections.push(make_unmatched_csv(output, separator));
+    }
+    if config.by_author {
+        sections.push(make_author_csv(output, config, separator));
+    }
+
+    sections.join("\n")
+}
+
+fn make_language_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    let sorted_counts = sort_counts(&output.counts);
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        std::iter::once("language".to_string())
+            .chain(columns.iter().map(|(name, _)| name.to_string())),
+        separator,
+    );
+
+    for (lang_id, counts) in &sorted_counts {
+        push_row(
+            &mut out,
+            std::iter::once(config.languages[*lang_id].name.clone())
+                .chain(columns.iter().map(|(_, get)| get(counts).to_string())),
+            separator,
+        );
+    }
+
+    if !config.no_total {
+        let mut total = Counts::default();
+        for (_, counts) in &sorted_counts {
+            total.merge(counts);
+        }
+
+        push_row(
+            &mut out,
+            std::iter::once("Total".to_string())
+                .chain(columns.iter().map(|(_, get)| get(&total).to_string())),
+            separator,
+        );
+    }
+
+    out
+}
+
+// not included in the per-language counts
+fn make_excluded_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        std::iter::once("excluded")
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for (category, counts) in &output.excluded {
+        push_row(
+            &mut out,
+            std::iter::once(category.name().to_string())
+                .chain(columns.iter().map(|(_, get)| get(counts).to_string())),
+            separator,
+        );
+    }
+
+    out
+}
+
+fn make_file_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    // skip the `files` column, it's always 1
+    let columns = &columns(config)[1..];
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["file", "language"]
+            .into_iter()
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for file in sort_files(output) {
+        push_row(
+            &mut out,
+            [
+                display_path(&file.path, config),
+                config.languages[file.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(columns.iter().map(|(_, get)| get(&file.counts).to_string())),
+            separator,
+        );
+    }
+
+    out
+}
+
+fn push_dir_rows(
+    out: &mut String,
+    path: &Path,
+    node: &DirNode,
+    columns: &[Column],
+    config: &Config,
+    separator: char,
+) {
+    let path_str = path.to_string_lossy();
+
+    if config.dir_languages {
+        for (lang_id, counts) in sort_counts(&node.languages) {
+            push_row(
+                out,
+                [path_str.to_string(), config.languages[lang_id].name.clone()]
+                    .into_iter()
+                    .chain(columns.iter().map(|(_, get)| get(counts).to_string())),
+                separator,
+            );
+        }
+    } else {
+        push_row(
+            out,
+            std::iter::once(path_str.to_string())
+                .chain(columns.iter().map(|(_, get)| get(&node.counts).to_string())),
+            separator,
+        );
+    }
+
+    for (child_name, child) in node.sorted_children() {
+        push_dir_rows(
+            out,
+            &path.join(child_name),
+            child,
+            columns,
+            config,
+            separator,
+        );
+    }
+}
+
+// with `dir_languages`, there is one row per directory and language
+fn make_dir_csv(tree: &DirNode, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let labels: &[&str] = if config.dir_languages {
+        &["directory", "language"]
+    } else {
+        &["directory"]
+    };
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        labels
+            .iter()
+            .chain(columns.iter().map(|(name, _)| name))
+            .map(|name| name.to_string()),
+        separator,
+    );
+
+    push_dir_rows(
+        &mut out,
+        &config.rel_root,
+        tree,
+        &columns,
+        config,
+        separator,
+    );
+
+    out
+}
+
+fn make_crate_csv(
+    crates: &BTreeMap<String, CrateCounts>,
+    config: &Config,
+    separator: char,
+) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["crate", "target"]
+            .into_iter()
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for (name, crate_counts) in sort_crates(crates) {
+        for (kind, counts) in crate_counts.sorted_targets() {
+            push_row(
+                &mut out,
+                [name.clone(), kind.to_string()]
+                    .into_iter()
+                    .chain(columns.iter().map(|(_, get)| get(counts).to_string())),
+                separator,
+            );
+        }
+    }
+
+    out
+}
+
+fn make_dep_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["dependency", "version", "kind"]
+            .into_iter()
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for dep in sort_deps(&output.deps) {
+        push_row(
+            &mut out,
+            [
+                dep.name.clone(),
+                dep.version.clone(),
+                format_kinds(&dep.kinds),
+            ]
+            .into_iter()
+            .chain(columns.iter().map(|(_, get)| get(&dep.counts).to_string())),
+            separator,
+        );
+    }
+
+    if !config.no_total {
+        let mut total = Counts::default();
+        for dep in &output.deps {
+            total.merge(&dep.counts);
+        }
+
+        push_row(
+            &mut out,
+            ["Total".to_string(), String::new(), String::new()]
+                .into_iter()
+                .chain(columns.iter().map(|(_, get)| get(&total).to_string())),
+            separator,
+        );
+    }
+
+    out
+}
+
+fn make_author_csv(output: &OutputCounts, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["author", "language"]
+            .into_iter()
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for author in sort_authors(&output.authors) {
+        push_row(
+            &mut out,
+            [
+                author.author.clone(),
+                config.languages[author.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(
+                columns
+                    .iter()
+                    .map(|(_, get)| get(&author.counts).to_string()),
+            ),
+            separator,
+        );
+    }
+
+    if !config.no_total {
+        // files are counted once for each of their authors
+        let mut total = Counts::default();
+        for author in &output.authors {
+            total.merge(&author.counts);
+        }
+        total.files = output.files.len();
+
+        push_row(
+            &mut out,
+            ["Total".to_string(), String::new()]
+                .into_iter()
+                .chain(columns.iter().map(|(_, get)| get(&total).to_string())),
+            separator,
+        );
+    }
+
+    out
+}
+
+fn make_unmatched_csv(output: &OutputCounts, separator: char) -> String {
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["unmatched", "files", "bytes"].map(str::to_string),
+        separator,
+    );
+
+    for (key, counts) in sort_unmatched(output) {
+        push_row(
+            &mut out,
+            [
+                key.clone(),
+                counts.files.to_string(),
+                counts.bytes.to_string(),
+            ],
+            separator,
+        );
+    }
+
+    out
+}
+
+// the old and new value of each column, the difference, and the change in percent (empty
+// if the old value is 0 but the new one isn't)
+fn diff_header(columns: &[Column]) -> impl Iterator<Item = String> {
+    columns.iter().flat_map(|(name, _)| {
+        [
+            format!("{name}_old"),
+            format!("{name}_new"),
+            format!("{name}_delta"),
+            format!("{name}_percent"),
+        ]
+    })
+}
+
+fn diff_fields<'a>(
+    columns: &'a [Column],
+    old: &'a Counts,
+    new: &'a Counts,
+) -> impl Iterator<Item = String> + 'a {
+    columns.iter().flat_map(|(_, get)| {
+        let (old, new) = (get(old), get(new));
+        [
+            old.to_string(),
+            new.to_string(),
+            delta(old, new).to_string(),
+            percent_change(old, new)
+                .map(|percent| format!("{percent:.1}"))
+                .unwrap_or_default(),
+        ]
+    })
+}
+
+// the per-file section is only added for the changed files with `by_file`
+pub fn make_diff_csv(diff: &DiffOutput, config: &Config, separator: char) -> String {
+    let mut sections = vec![make_language_diff_csv(diff, config, separator)];
+
+    if let Some(files) = &diff.files {
+        sections.push(make_file_diff_csv(files, config, separator));
+    }
+
+    sections.join("\n")
+}
+
+fn make_language_diff_csv(diff: &DiffOutput, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        std::iter::once("language".to_string()).chain(diff_header(&columns)),
+        separator,
+    );
+
+    let (old, new) = diff.total();
+    let rows = diff
+        .languages
+        .iter()
+        .map(|language| {
+            (
+                config.languages[language.lang_id].name.clone(),
+                &language.old,
+                &language.new,
+            )
+        })
+        .chain((!config.no_total).then(|| ("Total".to_string(), &old, &new)));
+
+    for (name, old, new) in rows {
+        push_row(
+            &mut out,
+            std::iter::once(name).chain(diff_fields(&columns, old, new)),
+            separator,
+        );
+    }
+
+    out
+}
+
+fn make_file_diff_csv(files: &[FileDiff], config: &Config, separator: char) -> String {
+    // skip the `files` column like in `make_file_csv`
+    let columns = &columns(config)[1..];
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["file".to_string(), "language".to_string()]
+            .into_iter()
+            .chain(diff_header(columns)),
+        separator,
+    );
+
+    for file in files {
+        push_row(
+            &mut out,
+            [
+                display_path(&file.path, config),
+                config.languages[file.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(diff_fields(columns, &file.old, &file.new)),
+            separator,
+        );
+    }
+
+    out
+}
+
+// one row per commit and language, followed by the total of the commit unless `no_total`
+pub fn make_history_csv(history: &HistoryOutput, config: &Config, separator: char) -> String {
+    let columns = columns(config);
+
+    let mut out = String::new();
+
+    push_row(
+        &mut out,
+        ["date", "commit", "language"]
+            .into_iter()
+            .chain(columns.iter().map(|(name, _)| *name))
+            .map(str::to_string),
+        separator,
+    );
+
+    for snapshot in &history.snapshots {
+        let total = snapshot.total();
+        let rows = snapshot
+            .languages
+            .iter()
+            .map(|(lang_id, counts)| (config.languages[*lang_id].name.clone(), counts))
+            .chain((!config.no_total).then(|| ("Total".to_string(), &total)));
+
+        for (name, counts) in rows {
+            push_row(
+                &mut out,
+                [format_date(snapshot.time), snapshot.commit.clone(), name]
+                    .into_iter()
+                    .chain(columns.iter().map(|(_, get)| get(counts).to_string())),
+                separator,
+            );
+        }
+    }
+
+    out
+}
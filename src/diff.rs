@@ -0,0 +1,177 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    AppError, ArgumentError, Countlines, Diff,
+    count::{Config, Counts, FileCounts, OutputCounts, run_count, run_index_count, run_rev_count},
+    languages::LanguageId,
+    parse_args,
+};
+
+/// The counts of one language on both sides of a diff
+pub struct LanguageDiff {
+    pub lang_id: LanguageId,
+    pub old: Counts,
+    pub new: Counts,
+}
+
+/// The counts of a changed file in its old and new version, an added or deleted file
+/// has empty counts in the version it's missing from
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub lang_id: LanguageId, // in the new version if there is one
+    pub old: Counts,
+    pub new: Counts,
+}
+
+pub struct DiffOutput {
+    pub old_name: String,
+    pub new_name: String,
+    pub languages: Vec<LanguageDiff>,
+    pub files: Option<Vec<FileDiff>>, // only for changed files, with `Config::by_file`
+    pub time: Duration,
+}
+
+impl DiffOutput {
+    // (old, new)
+    pub fn total(&self) -> (Counts, Counts) {
+        let mut old = Counts::default();
+        let mut new = Counts::default();
+        for language in &self.languages {
+            old.merge(&language.old);
+            new.merge(&language.new);
+        }
+        (old, new)
+    }
+}
+
+// a side is a directory if one exists at that path, otherwise a git revision
+// of the analyzed path
+fn side_config(args: &Countlines, side: &str) -> Result<Config, AppError> {
+    let mut args = args.clone();
+    args.command = None;
+    if Path::new(side).exists() {
+        args.path = Some(side.to_string());
+    } else {
+        args.rev = Some(side.to_string());
+    }
+    parse_args(&args)
+}
+
+fn count_side(config: &Config) -> Result<OutputCounts, AppError> {
+    match &config.rev {
+        Some(rev) => run_rev_count(config, rev),
+        None if config.counts_index() => run_index_count(config),
+        None => run_count(config),
+    }
+}
+
+// the config is the one of the new side, both sides use the same language pack
+pub fn run_diff(args: &Countlines, diff: &Diff) -> Result<(DiffOutput, Config), AppError> {
+    if args.changed_since.is_some() || args.staged {
+        return Err(ArgumentError::Incompatible("--changed-since/--staged", "diff").into());
+    }
+    if args.by_author {
+        return Err(ArgumentError::Incompatible("--by-author", "diff").into());
+    }
+
+    let old_config = side_config(args, &diff.old)?;
+    let new_config = side_config(args, &diff.new)?;
+
+    let start = Instant::now();
+    let old_output = count_side(&old_config)?;
+    let new_output = count_side(&new_config)?;
+    let time = start.elapsed();
+
+    let output = make_diff(&old_output, &new_output, &diff.old, &diff.new, time);
+    Ok((output, new_config))
+}
+
+// the old versions of the changed files are read from their base revision, deleted
+// files only have an old version and added files only have a new one
+pub fn run_changed_diff(config: &Config) -> Result<DiffOutput, AppError> {
+    let changed = config
+        .changed
+        .as_ref()
+        .expect("--with-old requires changed files");
+
+    let start = Instant::now();
+    let old_output = run_rev_count(config, &changed.base)?;
+    let new_output = count_side(config)?;
+    let time = start.elapsed();
+
+    let mut diff = make_diff(
+        &old_output,
+        &new_output,
+        &changed.base,
+        changed.target.name(),
+        time,
+    );
+    if config.by_file {
+        diff.files = Some(make_file_diffs(&old_output, &new_output));
+    }
+    Ok(diff)
+}
+
+// both outputs have the same root, so a file has the same path in both
+fn make_file_diffs(old_output: &OutputCounts, new_output: &OutputCounts) -> Vec<FileDiff> {
+    let mut versions = BTreeMap::<&Path, (Option<&FileCounts>, Option<&FileCounts>)>::new();
+    for file in &old_output.files {
+        versions.entry(&file.path).or_default().0 = Some(file);
+    }
+    for file in &new_output.files {
+        versions.entry(&file.path).or_default().1 = Some(file);
+    }
+
+    let mut files = versions
+        .into_iter()
+        .filter_map(|(path, (old, new))| {
+            Some(FileDiff {
+                path: path.to_path_buf(),
+                lang_id: new.or(old)?.lang_id,
+                old: old.map(|file| file.counts.clone()).unwrap_or_default(),
+                new: new.map(|file| file.counts.clone()).unwrap_or_default(),
+            })
+        })
+        .collect::<Vec<_>>();
+    // reverse order by size of the change in code lines, forward order by path
+    files.sort_by_key(|file| Reverse(file.old.code.abs_diff(file.new.code)));
+    files
+}
+
+fn make_diff(
+    old_output: &OutputCounts,
+    new_output: &OutputCounts,
+    old_name: &str,
+    new_name: &str,
+    time: Duration,
+) -> DiffOutput {
+    let lang_ids = old_output
+        .counts
+        .keys()
+        .chain(new_output.counts.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let mut languages = lang_ids
+        .into_iter()
+        .map(|lang_id| LanguageDiff {
+            lang_id,
+            old: old_output.counts.get(&lang_id).cloned().unwrap_or_default(),
+            new: new_output.counts.get(&lang_id).cloned().unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    // reverse order by size of the change in code lines, forward order by language
+    languages.sort_by_key(|language| Reverse(language.old.code.abs_diff(language.new.code)));
+
+    DiffOutput {
+        old_name: old_name.to_string(),
+        new_name: new_name.to_string(),
+        languages,
+        files: None,
+        time,
+    }
+}
@@ -0,0 +1,169 @@
+use std::{ffi::OsStr, sync::LazyLock};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+
+// matched against the file name
+const GENERATED_FILE_NAMES: &[&str] = &[
+    // protocol buffers, flatbuffers, grpc
+    "*.pb.go",
+    "*.pb.gw.go",
+    "*.pb.cc",
+    "*.pb.h",
+    "*_pb2.py",
+    "*_pb2_grpc.py",
+    "*_pb.js",
+    "*_pb.d.ts",
+    "*_generated.h",
+    // other code generators
+    "*.g.dart",
+    "*.freezed.dart",
+    "*.designer.cs",
+    "*.Designer.cs",
+    "zz_generated.*.go",
+    // minified assets
+    "*.min.js",
+    "*.min.css",
+    // lockfiles
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "composer.lock",
+    "Gemfile.lock",
+    "poetry.lock",
+    "Pipfile.lock",
+    "go.sum",
+    "flake.lock",
+];
+
+static GENERATED_FILE_NAME_SET: LazyLock<GlobSet> = LazyLock::new(|| {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in GENERATED_FILE_NAMES {
+        builder.add(Glob::new(pattern).unwrap());
+    }
+    builder.build().unwrap()
+});
+
+// generators mark their output with one of these header phrases near the top of the
+// file, matched against each line, a plain "do not edit" isn't enough since hand-written
+// files say it too (e.g. "do not edit below this line")
+const GENERATED_MARKERS: &[&str] = &[
+    r"@generated\b",
+    // go's convention, https://go.dev/s/generatedcode
+    r"^\W*Code generated .* DO NOT EDIT\.$",
+    r"^\W*Generated by the protocol buffer compiler\.\s+DO NOT EDIT!",
+    r"^\W*<auto-generated\b",
+    r"(?i)^\W*(this (file|code) (is|was|has been) )?auto-?generated\b.*\bdo not (edit|modify)\b",
+];
+
+static GENERATED_MARKER_SET: LazyLock<RegexSet> =
+    LazyLock::new(|| RegexSet::new(GENERATED_MARKERS).unwrap());
+
+// the number of lines searched for a marker
+const MARKER_LINES: usize = 5;
+
+// files with these extensions are considered minified if most of their beginning is on
+// lines longer than `MINIFIED_LINE_LEN`, hand-written files (even long tables of strings)
+// rarely have lines that long
+const MINIFIABLE_EXTENSIONS: &[&str] = &["js", "mjs", "cjs", "css"];
+const MINIFIED_LINE_LEN: usize = 500;
+
+// whether the file name alone says that the file is generated
+pub fn matches_file_name(file_name: &OsStr) -> bool {
+    GENERATED_FILE_NAME_SET.is_match(file_name)
+}
+
+// whether the beginning of the file says that it's generated or minified,
+// `extension` is the extension of the file, if any
+pub fn matches_head(extension: Option<&OsStr>, head: &str) -> bool {
+    let has_marker = head
+        .lines()
+        .take(MARKER_LINES)
+        .any(|line| GENERATED_MARKER_SET.is_match(line.trim_end()));
+    if has_marker {
+        return true;
+    }
+
+    let minifiable = extension
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| MINIFIABLE_EXTENSIONS.contains(&ext));
+    if !minifiable {
+        return false;
+    }
+    let long_lines_len: usize = head
+        .lines()
+        .map(str::len)
+        .filter(|len| *len > MINIFIED_LINE_LEN)
+        .sum();
+    long_lines_len > head.len() / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_generated(extension: &str, head: &str) -> bool {
+        matches_head(Some(OsStr::new(extension)), head)
+    }
+
+    #[test]
+    fn markers() {
+        assert!(is_generated(
+            "rs",
+            "// @generated by build.rs\nfn main() {}"
+        ));
+        assert!(is_generated(
+            "lock",
+            "# This file is automatically @generated by Cargo.\n# It is not intended for manual editing."
+        ));
+        assert!(is_generated(
+            "go",
+            "// Code generated by protoc-gen-go. DO NOT EDIT.\n\npackage foo"
+        ));
+        assert!(is_generated(
+            "h",
+            "// Generated by the protocol buffer compiler.  DO NOT EDIT!\n// source: foo.proto"
+        ));
+        assert!(is_generated(
+            "cs",
+            "// <auto-generated>\n//     tool\n// </auto-generated>"
+        ));
+        assert!(is_generated(
+            "ts",
+            "/* eslint-disable */\n// AUTO-GENERATED FILE, DO NOT EDIT.\nexport {};"
+        ));
+    }
+
+    #[test]
+    fn hand_written_headers() {
+        assert!(!is_generated("sh", "# Do not edit below this line\nfoo=1"));
+        assert!(!is_generated(
+            "go",
+            "// DO NOT EDIT this table by hand, see below\npackage foo"
+        ));
+        assert!(!is_generated(
+            "py",
+            "# Helpers for auto-generated docs\nimport os"
+        ));
+        // markers are only looked for in the first lines
+        assert!(!is_generated(
+            "rs",
+            "fn a() {}\n\n\n\n\n// @generated\nfn b() {}"
+        ));
+    }
+
+    #[test]
+    fn minified() {
+        let minified = format!("!function(){{{}}}();", "var a=1;".repeat(500));
+        assert!(is_generated("js", &minified));
+        assert!(!is_generated("rs", &minified));
+
+        // a hand-written table with long lines isn't minified
+        let row = format!("  \"{}\",\n", "x".repeat(150));
+        let table = format!("const TABLE = [\n{}];\n", row.repeat(20));
+        assert!(!is_generated("js", &table));
+        assert!(!is_generated("js", ""));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GitError {
+    #[error("failed to run git")]
+    Io(#[from] std::io::Error),
+
+    #[error("git {command} failed: {stderr}")]
+    Command {
+        command: &'static str,
+        stderr: String,
+    },
+
+    #[error("unexpected output from git {0}")]
+    Output(&'static str),
+}
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(dir);
+    command
+}
+
+// runs a git command to completion and returns its stdout
+fn output(dir: &Path, command: &'static str, args: &[&str]) -> Result<Vec<u8>, GitError> {
+    let output = git(dir).arg(command).args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(GitError::Command {
+            command,
+            stderr: stderr.trim().to_string(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+/// A file in a git tree
+pub struct TreeEntry {
+    pub path: PathBuf, // relative to the directory `ls_tree` was run in
+    pub oid: String,
+    pub size: u64,
+}
+
+// the files (not symlinks or submodules) of `rev` under `dir`, like `ls -R`
+pub fn ls_tree(dir: &Path, rev: &str) -> Result<Vec<TreeEntry>, GitError> {
+    let stdout = output(dir, "ls-tree", &["-r", "-l", "-z", rev, "--", "."])?;
+
+    let mut entries = Vec::new();
+    // `<mode> SP <type> SP <oid> SP+ <size> TAB <path> NUL`
+    for record in stdout
+        .split(|b| *b == 0)
+        .filter(|record| !record.is_empty())
+    {
+        let record = String::from_utf8_lossy(record);
+        let (meta, path) = record.split_once('\t').ok_or(GitError::Output("ls-tree"))?;
+        let mut meta = meta.split_whitespace();
+        let (Some(mode), Some(kind), Some(oid), Some(size)) =
+            (meta.next(), meta.next(), meta.next(), meta.next())
+        else {
+            return Err(GitError::Output("ls-tree"));
+        };
+
+        if kind != "blob" || mode == "120000" {
+            continue;
+        }
+        entries.push(TreeEntry {
+            path: PathBuf::from(path),
+            oid: oid.to_string(),
+            size: size.parse().map_err(|_| GitError::Output("ls-tree"))?,
+        });
+    }
+
+    Ok(entries)
+}
+
+// the files (not symlinks or submodules) of the index under `dir` for which `keep` returns
+// true given their path, like `ls_tree`, unmerged files are skipped
+pub fn ls_index(dir: &Path, keep: impl Fn(&Path) -> bool) -> Result<Vec<TreeEntry>, GitError> {
+    let stdout = output(dir, "ls-files", &["--stage", "-z", "--", "."])?;
+
+    // the index doesn't record the size of blobs
+    let mut child = git(dir)
+        .args(["cat-file", "--batch-check"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or(GitError::Output("cat-file"))?;
+    let mut sizes = BufReader::new(child.stdout.take().ok_or(GitError::Output("cat-file"))?);
+
+    let mut entries = Vec::new();
+    // `<mode> SP <oid> SP <stage> TAB <path> NUL`
+    for record in stdout
+        .split(|b| *b == 0)
+        .filter(|record| !record.is_empty())
+    {
+        let record = String::from_utf8_lossy(record);
+        let (meta, path) = record
+            .split_once('\t')
+            .ok_or(GitError::Output("ls-files"))?;
+        let mut meta = meta.split_whitespace();
+        let (Some(mode), Some(oid), Some(stage)) = (meta.next(), meta.next(), meta.next()) else {
+            return Err(GitError::Output("ls-files"));
+        };
+
+        let path = PathBuf::from(path);
+        // only the sizes of the kept files are looked up
+        if mode == "120000" || mode == "160000" || stage != "0" || !keep(&path) {
+            continue;
+        }
+
+        // `<oid> SP <type> SP <size> LF`
+        writeln!(stdin, "{oid}")?;
+        stdin.flush()?;
+        let mut line = String::new();
+        sizes.read_line(&mut line)?;
+        let size = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse().map_err(|_| GitError::Output("cat-file"))?,
+            _ => return Err(GitError::Output("cat-file")),
+        };
+
+        entries.push(TreeEntry {
+            path,
+            oid: oid.to_string(),
+            size,
+        });
+    }
+
+    drop(stdin);
+    child.wait()?;
+
+    Ok(entries)
+}
+
+/// Reads blobs from the object database through a long running `git cat-file --batch`
+pub struct BlobReader {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BlobReader {
+    pub fn new(dir: &Path) -> Result<Self, GitError> {
+        let mut child = git(dir)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(GitError::Output("cat-file"))?;
+        let stdout = child.stdout.take().ok_or(GitError::Output("cat-file"))?;
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    pub fn read(&mut self, oid: &str) -> Result<Vec<u8>, GitError> {
+        writeln!(self.stdin, "{oid}")?;
+        self.stdin.flush()?;
+
+        // `<oid> SP <type> SP <size> LF <contents> LF`, or `<oid> SP missing LF`
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size
+                .parse::<usize>()
+                .map_err(|_| GitError::Output("cat-file"))?,
+            _ => return Err(GitError::Output("cat-file")),
+        };
+
+        let mut contents = vec![0; size + 1];
+        self.stdout.read_exact(&mut contents)?;
+        contents.pop(); // trailing LF
+        Ok(contents)
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        // don't leave cat-file running if counting stopped early
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A commit on the first-parent history of a revision
+pub struct Commit {
+    pub oid: String,
+    pub time: i64, // committer date, seconds since the epoch
+}
+
+// oldest first, merged branches are skipped like with `git log --first-parent`
+pub fn log(dir: &Path, rev: &str) -> Result<Vec<Commit>, GitError> {
+    let stdout = output(
+        dir,
+        "log",
+        &["--first-parent", "--format=%H %ct", rev, "--"],
+    )?;
+
+    let mut commits = String::from_utf8_lossy(&stdout)
+        .lines()
+        .map(|line| {
+            let (oid, time) = line.split_once(' ').ok_or(GitError::Output("log"))?;
+            Ok(Commit {
+                oid: oid.to_string(),
+                time: time.parse().map_err(|_| GitError::Output("log"))?,
+            })
+        })
+        .collect::<Result<Vec<_>, GitError>>()?;
+    commits.reverse();
+
+    Ok(commits)
+}
+
+/// What the base revision of `changed_files` is compared to
+pub enum ChangeTarget {
+    WorkTree, // including untracked files
+    Index,
+    Rev(String),
+}
+
+impl ChangeTarget {
+    pub fn name(&self) -> &str {
+        match self {
+            ChangeTarget::WorkTree => "working tree",
+            ChangeTarget::Index => "index",
+            ChangeTarget::Rev(rev) => rev,
+        }
+    }
+}
+
+/// The files that differ between a base revision and a target
+pub struct ChangedFiles {
+    pub base: String,
+    pub target: ChangeTarget,
+    pub paths: HashSet<PathBuf>, // relative to the directory `changed_files` was run in
+}
+
+// added, modified, and deleted files under `dir`, a renamed file is both deleted and added
+pub fn changed_files(
+    dir: &Path,
+    base: &str,
+    target: ChangeTarget,
+) -> Result<ChangedFiles, GitError> {
+    let mut args = vec!["--name-only", "-z", "--no-renames", "--relative"];
+    match &target {
+        ChangeTarget::WorkTree => args.push(base),
+        ChangeTarget::Index => args.extend(["--cached", base]),
+        ChangeTarget::Rev(rev) => args.extend([base, rev]),
+    }
+    args.push("--");
+    let mut stdout = output(dir, "diff", &args)?;
+
+    if let ChangeTarget::WorkTree = target {
+        stdout.extend(output(
+            dir,
+            "ls-files",
+            &["--others", "--exclude-standard", "-z", "--", "."],
+        )?);
+    }
+
+    let paths = stdout
+        .split(|b| *b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+        .collect();
+
+    Ok(ChangedFiles {
+        base: base.to_string(),
+        target,
+        paths,
+    })
+}
+
+// whether `dir` is inside the work tree of a git repository
+pub fn is_work_tree(dir: &Path) -> Result<bool, GitError> {
+    match output(dir, "rev-parse", &["--is-inside-work-tree"]) {
+        Ok(stdout) => Ok(stdout.trim_ascii() == b"true"),
+        Err(GitError::Command { .. }) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// whether `path` (relative to `dir`) is tracked, i.e. in the index
+pub fn is_tracked(dir: &Path, path: &Path) -> Result<bool, GitError> {
+    let pathspec = format!(":(literal){}", path.to_string_lossy());
+    let stdout = output(dir, "ls-files", &["-z", "--", &pathspec])?;
+    Ok(!stdout.is_empty())
+}
+
+// the author git reports for lines that aren't committed
+pub const NOT_COMMITTED: &str = "Not Committed Yet";
+
+/// The contents of a file and the author of each of its lines
+pub struct Blame {
+    pub authors: Vec<String>,
+    pub contents: Vec<u8>,
+}
+
+// blames `path` (relative to `dir`) as it is in `rev`, or in the working directory if
+// `rev` is `None`, authors are mapped through .mailmap by git
+pub fn blame(dir: &Path, path: &Path, rev: Option<&str>) -> Result<Blame, GitError> {
+    let path = path.to_string_lossy();
+    let mut args = vec!["--line-porcelain"];
+    args.extend(rev);
+    args.extend(["--", &path]);
+    let stdout = output(dir, "blame", &args)?;
+
+    let mut blame = Blame {
+        authors: Vec::new(),
+        contents: Vec::new(),
+    };
+    let mut author = None;
+    // a header for every line, `<key> SP <value>` fields, then `TAB <line>`
+    for line in stdout.split(|b| *b == b'\n') {
+        if let Some(line) = line.strip_prefix(b"\t") {
+            let author = author.take().ok_or(GitError::Output("blame"))?;
+            blame.authors.push(author);
+            blame.contents.extend_from_slice(line);
+            blame.contents.push(b'\n');
+        } else if let Some(name) = line.strip_prefix(b"author ") {
+            author = Some(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+
+    Ok(blame)
+}
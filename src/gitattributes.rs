@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use globset::{GlobBuilder, GlobMatcher};
+use log::warn;
+use serde::Serialize;
+
+/// Files that GitHub's linguist leaves out of the language statistics, they are
+/// counted separately
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Vendored,
+    Generated,
+    Documentation,
+}
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Vendored => "Vendored",
+            Category::Generated => "Generated",
+            Category::Documentation => "Documentation",
+        }
+    }
+}
+
+/// The linguist attributes that apply to a file, `None` if unspecified
+#[derive(Default)]
+pub struct Overrides {
+    pub language: Option<String>, // `linguist-language=<name>`
+    vendored: Option<bool>,
+    pub generated: Option<bool>,
+    documentation: Option<bool>,
+}
+
+impl Overrides {
+    pub fn category(&self) -> Option<Category> {
+        if self.vendored == Some(true) {
+            Some(Category::Vendored)
+        } else if self.generated == Some(true) {
+            Some(Category::Generated)
+        } else if self.documentation == Some(true) {
+            Some(Category::Documentation)
+        } else {
+            None
+        }
+    }
+}
+
+enum Attribute {
+    Language,
+    Vendored,
+    Generated,
+    Documentation,
+}
+
+struct Rule {
+    matcher: GlobMatcher,
+    // patterns without a slash match the file name at any depth, the others
+    // match the path relative to the directory of the .gitattributes file
+    file_name_only: bool,
+    // `None` if unspecified (`!attr`), `attr` and `-attr` are "true" and "false"
+    attributes: Vec<(Attribute, Option<String>)>,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let pattern = words.next()?;
+        // negative patterns are forbidden, patterns matching directories don't
+        // apply to the files inside them
+        if pattern.starts_with('!') || pattern.ends_with('/') {
+            return None;
+        }
+
+        let attributes = words
+            .filter_map(|word| {
+                let (name, value) = if let Some(name) = word.strip_prefix('-') {
+                    (name, Some("false".to_string()))
+                } else if let Some(name) = word.strip_prefix('!') {
+                    (name, None)
+                } else if let Some((name, value)) = word.split_once('=') {
+                    (name, Some(value.to_string()))
+                } else {
+                    (word, Some("true".to_string()))
+                };
+                let attribute = match name {
+                    "linguist-language" => Attribute::Language,
+                    "linguist-vendored" => Attribute::Vendored,
+                    "linguist-generated" => Attribute::Generated,
+                    "linguist-documentation" => Attribute::Documentation,
+                    _ => return None,
+                };
+                Some((attribute, value))
+            })
+            .collect::<Vec<_>>();
+        if attributes.is_empty() {
+            return None;
+        }
+
+        let file_name_only = !pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        let matcher = match GlobBuilder::new(pattern).literal_separator(true).build() {
+            Ok(glob) => glob.compile_matcher(),
+            Err(err) => {
+                warn!("invalid pattern {pattern:?} in .gitattributes: {err}");
+                return None;
+            }
+        };
+
+        Some(Self {
+            matcher,
+            file_name_only,
+            attributes,
+        })
+    }
+
+    fn is_match(&self, path: &Path, dir: &Path) -> bool {
+        if self.file_name_only {
+            path.file_name()
+                .is_some_and(|file_name| self.matcher.is_match(file_name))
+        } else {
+            path.strip_prefix(dir)
+                .is_ok_and(|rel_path| self.matcher.is_match(rel_path))
+        }
+    }
+
+    fn apply(&self, overrides: &mut Overrides) {
+        for (attribute, value) in &self.attributes {
+            let is_set = value.as_deref().map(|value| value == "true");
+            match attribute {
+                Attribute::Language => {
+                    overrides.language = value.clone().filter(|_| is_set != Some(true));
+                }
+                Attribute::Vendored => overrides.vendored = is_set,
+                Attribute::Generated => overrides.generated = is_set,
+                Attribute::Documentation => overrides.documentation = is_set,
+            }
+        }
+    }
+}
+
+/// The linguist overrides in the .gitattributes files under a root directory,
+/// each file is parsed the first time a file in its directory is counted
+pub struct GitAttributes {
+    root: PathBuf,
+    rules: Mutex<HashMap<PathBuf, Arc<[Rule]>>>, // by directory
+    read_files: bool, // whether directories missing from `rules` are read from disk
+}
+
+fn parse_rules(text: &str) -> Arc<[Rule]> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(Rule::parse)
+        .collect()
+}
+
+impl GitAttributes {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            rules: Mutex::new(HashMap::new()),
+            read_files: true,
+        }
+    }
+
+    // from the contents of all the .gitattributes files, by directory, e.g. read from git
+    pub fn from_files(root: &Path, files: impl IntoIterator<Item = (PathBuf, String)>) -> Self {
+        let rules = files
+            .into_iter()
+            .map(|(dir, text)| (dir, parse_rules(&text)))
+            .collect();
+        Self {
+            root: root.to_path_buf(),
+            rules: Mutex::new(rules),
+            read_files: false,
+        }
+    }
+
+    fn rules(&self, dir: &Path) -> Arc<[Rule]> {
+        if let Some(rules) = self.rules.lock().unwrap().get(dir) {
+            return rules.clone();
+        }
+        if !self.read_files {
+            return Arc::new([]);
+        }
+
+        // a missing or unreadable file has no rules
+        let rules = fs::read_to_string(dir.join(".gitattributes"))
+            .map(|text| parse_rules(&text))
+            .unwrap_or_default();
+
+        self.rules
+            .lock()
+            .unwrap()
+            .entry(dir.to_path_buf())
+            .or_insert(rules)
+            .clone()
+    }
+
+    // deeper .gitattributes files and later lines take precedence, files above
+    // the root are not considered
+    pub fn overrides(&self, path: &Path) -> Overrides {
+        let mut overrides = Overrides::default();
+
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .collect::<Vec<_>>();
+        for dir in dirs.into_iter().rev() {
+            for rule in self.rules(dir).iter() {
+                if rule.is_match(path, dir) {
+                    rule.apply(&mut overrides);
+                }
+            }
+        }
+
+        overrides
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::Languages;
+
+    fn attributes(files: &[(&str, &str)]) -> GitAttributes {
+        GitAttributes::from_files(
+            Path::new("/repo"),
+            files
+                .iter()
+                .map(|(dir, text)| (PathBuf::from(dir), text.to_string())),
+        )
+    }
+
+    fn category(attributes: &GitAttributes, path: &str) -> Option<Category> {
+        attributes.overrides(Path::new(path)).category()
+    }
+
+    #[test]
+    fn negation() {
+        let attributes = attributes(&[(
+            "/repo",
+            "*.js linguist-vendored\nmain.js -linguist-vendored\nlib.js !linguist-vendored",
+        )]);
+        assert_eq!(
+            category(&attributes, "/repo/jquery.js"),
+            Some(Category::Vendored)
+        );
+        assert_eq!(category(&attributes, "/repo/src/main.js"), None);
+        assert_eq!(
+            attributes.overrides(Path::new("/repo/main.js")).vendored,
+            Some(false)
+        );
+        assert_eq!(
+            attributes.overrides(Path::new("/repo/lib.js")).vendored,
+            None
+        );
+    }
+
+    #[test]
+    fn nested_precedence() {
+        let attributes = attributes(&[
+            (
+                "/repo",
+                "*.md linguist-documentation\n/sub/*.c linguist-generated",
+            ),
+            (
+                "/repo/sub",
+                "*.md -linguist-documentation\nkeep.c -linguist-generated",
+            ),
+        ]);
+        assert_eq!(
+            category(&attributes, "/repo/README.md"),
+            Some(Category::Documentation)
+        );
+        assert_eq!(category(&attributes, "/repo/sub/README.md"), None);
+        assert_eq!(category(&attributes, "/repo/sub/other/README.md"), None);
+        assert_eq!(
+            category(&attributes, "/repo/sub/parser.c"),
+            Some(Category::Generated)
+        );
+        assert_eq!(category(&attributes, "/repo/sub/keep.c"), None);
+        // `/sub/*.c` is relative to the root, it doesn't match deeper files
+        assert_eq!(category(&attributes, "/repo/sub/deep/parser.c"), None);
+    }
+
+    #[test]
+    fn language_lookup() {
+        let languages = Languages::builtin();
+        let attributes = attributes(&[
+            (
+                "/repo",
+                "*.inc linguist-language=rust\n*.h linguist-language=C++",
+            ),
+            ("/repo/sub", "*.inc -linguist-language"),
+        ]);
+        let language = |path| {
+            let language = attributes.overrides(Path::new(path)).language?;
+            let lang_id = languages.find_by_name(&language)?;
+            Some(languages[lang_id].name.clone())
+        };
+        assert_eq!(language("/repo/a.inc").as_deref(), Some("Rust"));
+        assert_eq!(language("/repo/a.h").as_deref(), Some("C++"));
+        assert_eq!(language("/repo/sub/a.inc"), None);
+        assert_eq!(language("/repo/a.rs"), None);
+    }
+}
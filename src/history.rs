@@ -0,0 +1,211 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    AppError, ArgumentError, Countlines, History, Period,
+    count::{BlobCache, Config, Counts, count_rev, make_pbar},
+    git::{self, BlobReader, Commit},
+    languages::LanguageId,
+    parse_args,
+    util::civil_from_days,
+};
+
+/// The counts of the analyzed path at one commit
+pub struct Snapshot {
+    pub commit: String,
+    pub time: i64, // committer date, seconds since the epoch
+    pub languages: BTreeMap<LanguageId, Counts>,
+}
+
+impl Snapshot {
+    pub fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        for counts in self.languages.values() {
+            total.merge(counts);
+        }
+        total
+    }
+}
+
+pub struct HistoryOutput {
+    pub snapshots: Vec<Snapshot>, // oldest first
+    pub time: Duration,
+}
+
+// commits in the same period have the same key
+fn period_key(period: Period, time: i64) -> i64 {
+    let days = time.div_euclid(86400);
+    match period {
+        Period::Day => days,
+        // 1970-01-01 was a Thursday
+        Period::Week => days - (days + 3).rem_euclid(7),
+        Period::Month => {
+            let (year, month, _) = civil_from_days(days);
+            year * 12 + i64::from(month)
+        }
+    }
+}
+
+// the commits to count, the latest commit is always included
+fn sample(commits: Vec<Commit>, history: &History) -> Vec<Commit> {
+    let len = commits.len();
+    let keys = commits
+        .iter()
+        .map(|commit| history.per.map(|period| period_key(period, commit.time)))
+        .collect::<Vec<_>>();
+
+    commits
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| match (history.every, history.per) {
+            (Some(every), _) => (len - 1 - i).is_multiple_of(every),
+            // the last commit of its period
+            (None, Some(_)) => *i == len - 1 || keys[*i] != keys[i + 1],
+            (None, None) => true,
+        })
+        .map(|(_, commit)| commit)
+        .collect()
+}
+
+// the config is the one of the analyzed path, the history starts at --rev if given
+pub fn run_history(
+    args: &Countlines,
+    history: &History,
+) -> Result<(HistoryOutput, Config), AppError> {
+    if history.every.is_some() && history.per.is_some() {
+        return Err(ArgumentError::Incompatible("--every", "--per").into());
+    }
+    if history.every == Some(0) {
+        return Err(ArgumentError::Zero("--every").into());
+    }
+    if args.deps {
+        return Err(ArgumentError::Incompatible("--deps", "history").into());
+    }
+    if args.changed_since.is_some() || args.staged {
+        return Err(ArgumentError::Incompatible("--changed-since/--staged", "history").into());
+    }
+    if args.by_author {
+        return Err(ArgumentError::Incompatible("--by-author", "history").into());
+    }
+
+    let mut args = args.clone();
+    args.command = None;
+    let config = parse_args(&args)?;
+
+    let start = Instant::now();
+
+    let root = &config.abs_root;
+    let commits = sample(
+        git::log(root, config.rev.as_deref().unwrap_or("HEAD"))?,
+        history,
+    );
+
+    // unchanged files are counted once for the whole history
+    let mut blobs = BlobReader::new(root)?;
+    let mut cache = BlobCache::default();
+    let pbar = make_pbar(&config);
+
+    let mut snapshots = Vec::with_capacity(commits.len());
+    for commit in commits {
+        let output = count_rev(&config, &commit.oid, &mut blobs, &mut cache, pbar.as_ref())?;
+        snapshots.push(Snapshot {
+            commit: commit.oid,
+            time: commit.time,
+            languages: output.counts.into_iter().collect(),
+        });
+    }
+
+    if let Some(pbar) = &pbar {
+        pbar.finish_and_clear();
+    }
+
+    let output = HistoryOutput {
+        snapshots,
+        time: start.elapsed(),
+    };
+    Ok((output, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86400;
+
+    // the indices of the sampled commits, given the time of each commit
+    fn sample_times(times: &[i64], every: Option<usize>, per: Option<Period>) -> Vec<usize> {
+        let commits = times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| Commit {
+                oid: i.to_string(),
+                time: *time,
+            })
+            .collect();
+        sample(commits, &History { every, per })
+            .into_iter()
+            .map(|commit| commit.oid.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn week_starts_on_monday() {
+        // 2024-01-28 was a Sunday
+        let sunday = 19750 * DAY;
+        let monday = sunday + DAY;
+        assert_eq!(period_key(Period::Week, sunday), 19750 - 6);
+        assert_eq!(period_key(Period::Week, monday), 19751);
+        assert_eq!(period_key(Period::Week, monday + 7 * DAY - 1), 19751);
+        // 1969-12-29 was a Monday
+        assert_eq!(period_key(Period::Week, 0), -3);
+        assert_eq!(period_key(Period::Week, -1), -3);
+    }
+
+    #[test]
+    fn period_boundaries() {
+        let end_of_january = 1706745599; // 2024-01-31 23:59:59
+        assert_ne!(
+            period_key(Period::Day, end_of_january),
+            period_key(Period::Day, end_of_january + 1)
+        );
+        assert_ne!(
+            period_key(Period::Month, end_of_january),
+            period_key(Period::Month, end_of_january + 1)
+        );
+        assert_eq!(
+            period_key(Period::Month, end_of_january),
+            period_key(Period::Month, end_of_january - 30 * DAY)
+        );
+        // december and the next january
+        assert_eq!(
+            period_key(Period::Month, -1) + 1,
+            period_key(Period::Month, 0)
+        );
+    }
+
+    #[test]
+    fn sample_every() {
+        let times = [0, 1, 2, 3, 4];
+        assert_eq!(sample_times(&times, Some(2), None), [0, 2, 4]);
+        assert_eq!(sample_times(&times, Some(3), None), [1, 4]);
+        assert_eq!(sample_times(&times, Some(10), None), [4]);
+        assert_eq!(sample_times(&times, None, None), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn sample_per_period() {
+        let times = [10, DAY - 1, DAY, DAY + 10, 3 * DAY];
+        assert_eq!(sample_times(&times, None, Some(Period::Day)), [1, 3, 4]);
+
+        // the last second of a sunday and the first of the next monday
+        let sunday = 19750 * DAY;
+        let times = [sunday, sunday + DAY - 1, sunday + DAY, sunday + 2 * DAY];
+        assert_eq!(sample_times(&times, None, Some(Period::Week)), [1, 3]);
+
+        let end_of_january = 1706745599;
+        let times = [end_of_january - DAY, end_of_january, end_of_january + 1];
+        assert_eq!(sample_times(&times, None, Some(Period::Month)), [1, 2]);
+    }
+}
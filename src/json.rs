@@ -0,0 +1,319 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    Mode,
+    authors::sort_authors,
+    cargo::{CrateCounts, DepCounts, make_crate_counts, sort_deps},
+    count::{Config, Counts, OutputCounts, UnmatchedCounts, display_path},
+    diff::DiffOutput,
+    gitattributes::Category,
+    history::HistoryOutput,
+    languages::LanguageId,
+    table::{sort_files, sort_unmatched},
+    tree::{DirNode, make_tree},
+    util::{delta, format_date, percent_change},
+};
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    root: &'a Path,
+    mode: Mode,
+    elapsed_secs: f64,
+    languages: BTreeMap<&'a str, &'a Counts>,
+    total: Counts,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    excluded: &'a BTreeMap<Category, Counts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<JsonFile<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dirs: Option<JsonDir<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crates: Option<BTreeMap<String, CrateCounts>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Vec<&'a DepCounts>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unmatched: Option<Vec<JsonUnmatched<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authors: Option<Vec<JsonAuthor<'a>>>,
+    unmatched_files: usize,
+    error_files: usize,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    author: &'a str,
+    language: &'a str,
+    #[serde(flatten)]
+    counts: &'a Counts,
+}
+
+#[derive(Serialize)]
+struct JsonUnmatched<'a> {
+    key: &'a str,
+    #[serde(flatten)]
+    counts: &'a UnmatchedCounts,
+}
+
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    path: String,
+    language: &'a str,
+    #[serde(flatten)]
+    counts: &'a Counts,
+}
+
+#[derive(Serialize)]
+struct JsonDir<'a> {
+    name: String,
+    #[serde(flatten)]
+    counts: &'a Counts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    languages: Option<BTreeMap<&'a str, &'a Counts>>,
+    children: Vec<JsonDir<'a>>,
+}
+
+fn language_names<'a>(
+    counts: &'a HashMap<LanguageId, Counts>,
+    config: &'a Config,
+) -> BTreeMap<&'a str, &'a Counts> {
+    counts
+        .iter()
+        .map(|(lang_id, counts)| (config.languages[*lang_id].name.as_str(), counts))
+        .collect()
+}
+
+fn make_json_dir<'a>(name: String, node: &'a DirNode, config: &'a Config) -> JsonDir<'a> {
+    JsonDir {
+        name,
+        counts: &node.counts,
+        languages: config
+            .dir_languages
+            .then(|| language_names(&node.languages, config)),
+        children: node
+            .sorted_children()
+            .into_iter()
+            .map(|(child_name, child)| make_json_dir(child_name.clone(), child, config))
+            .collect(),
+    }
+}
+
+pub fn make_json(
+    output: &OutputCounts,
+    config: &Config,
+    time: Duration,
+) -> Result<String, serde_json::Error> {
+    let mut total = Counts::default();
+    for counts in output.counts.values() {
+        total.merge(counts);
+    }
+
+    let languages = language_names(&output.counts, config);
+
+    let files = config.by_file.then(|| {
+        sort_files(output)
+            .into_iter()
+            .map(|file| JsonFile {
+                path: display_path(&file.path, config),
+                language: &config.languages[file.lang_id].name,
+                counts: &file.counts,
+            })
+            .collect()
+    });
+
+    let tree = config
+        .by_dir
+        .map(|max_depth| make_tree(output, config, max_depth));
+    let dirs = tree
+        .as_ref()
+        .map(|tree| make_json_dir(config.rel_root.to_string_lossy().into_owned(), tree, config));
+
+    let crates = config
+        .workspace
+        .as_ref()
+        .map(|workspace| make_crate_counts(output, workspace, config));
+
+    let dependencies = config.deps.as_ref().map(|_| sort_deps(&output.deps));
+
+    let unmatched = config.unmatched.then(|| {
+        sort_unmatched(output)
+            .into_iter()
+            .map(|(key, counts)| JsonUnmatched { key, counts })
+            .collect()
+    });
+
+    let authors = config.by_author.then(|| {
+        sort_authors(&output.authors)
+            .into_iter()
+            .map(|author| JsonAuthor {
+                author: &author.author,
+                language: &config.languages[author.lang_id].name,
+                counts: &author.counts,
+            })
+            .collect()
+    });
+
+    serde_json::to_string_pretty(&JsonOutput {
+        root: &config.abs_root,
+        mode: config.mode,
+        elapsed_secs: time.as_secs_f64(),
+        languages,
+        total,
+        excluded: &output.excluded,
+        files,
+        dirs,
+        crates,
+        dependencies,
+        unmatched,
+        authors,
+        unmatched_files: output.unmatched_files,
+        error_files: output.error_files,
+    })
+}
+
+#[derive(Serialize)]
+struct JsonDiff<'a> {
+    old: &'a str,
+    new: &'a str,
+    elapsed_secs: f64,
+    languages: BTreeMap<&'a str, JsonLanguageDiff<'a>>,
+    total: JsonLanguageDiff<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<JsonFileDiff<'a>>>,
+}
+
+#[derive(Serialize)]
+struct JsonFileDiff<'a> {
+    path: String,
+    language: &'a str,
+    #[serde(flatten)]
+    diff: JsonLanguageDiff<'a>,
+}
+
+#[derive(Serialize)]
+struct JsonLanguageDiff<'a> {
+    old: &'a Counts,
+    new: &'a Counts,
+    delta: JsonChange<i64>,
+    percent: JsonChange<Option<f64>>, // `null` if the old count is 0 but the new one isn't
+}
+
+impl<'a> JsonLanguageDiff<'a> {
+    fn new(old: &'a Counts, new: &'a Counts) -> Self {
+        Self {
+            old,
+            new,
+            delta: JsonChange::new(old, new, delta),
+            percent: JsonChange::new(old, new, percent_change),
+        }
+    }
+}
+
+// the change of each count, with the same fields as `Counts`
+#[derive(Serialize)]
+struct JsonChange<T> {
+    files: T,
+    code: T,
+    test_code: T,
+    comment: T,
+    doc: T,
+    mixed: T,
+    blank: T,
+    invalid: T,
+}
+
+impl<T> JsonChange<T> {
+    fn new(old: &Counts, new: &Counts, change: fn(usize, usize) -> T) -> Self {
+        Self {
+            files: change(old.files, new.files),
+            code: change(old.code, new.code),
+            test_code: change(old.test_code, new.test_code),
+            comment: change(old.comment, new.comment),
+            doc: change(old.doc, new.doc),
+            mixed: change(old.mixed, new.mixed),
+            blank: change(old.blank, new.blank),
+            invalid: change(old.invalid, new.invalid),
+        }
+    }
+}
+
+pub fn make_diff_json(diff: &DiffOutput, config: &Config) -> Result<String, serde_json::Error> {
+    let (old, new) = diff.total();
+
+    let languages = diff
+        .languages
+        .iter()
+        .map(|language| {
+            (
+                config.languages[language.lang_id].name.as_str(),
+                JsonLanguageDiff::new(&language.old, &language.new),
+            )
+        })
+        .collect();
+
+    let files = diff.files.as_ref().map(|files| {
+        files
+            .iter()
+            .map(|file| JsonFileDiff {
+                path: display_path(&file.path, config),
+                language: &config.languages[file.lang_id].name,
+                diff: JsonLanguageDiff::new(&file.old, &file.new),
+            })
+            .collect()
+    });
+
+    serde_json::to_string_pretty(&JsonDiff {
+        old: &diff.old_name,
+        new: &diff.new_name,
+        elapsed_secs: diff.time.as_secs_f64(),
+        languages,
+        total: JsonLanguageDiff::new(&old, &new),
+        files,
+    })
+}
+
+#[derive(Serialize)]
+struct JsonHistory<'a> {
+    elapsed_secs: f64,
+    commits: Vec<JsonSnapshot<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSnapshot<'a> {
+    commit: &'a str,
+    date: String,
+    timestamp: i64,
+    languages: BTreeMap<&'a str, &'a Counts>,
+    total: Counts,
+}
+
+pub fn make_history_json(
+    history: &HistoryOutput,
+    config: &Config,
+) -> Result<String, serde_json::Error> {
+    let commits = history
+        .snapshots
+        .iter()
+        .map(|snapshot| JsonSnapshot {
+            commit: &snapshot.commit,
+            date: format_date(snapshot.time),
+            timestamp: snapshot.time,
+            languages: snapshot
+                .languages
+                .iter()
+                .map(|(lang_id, counts)| (config.languages[*lang_id].name.as_str(), counts))
+                .collect(),
+            total: snapshot.total(),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&JsonHistory {
+        elapsed_secs: history.time.as_secs_f64(),
+        commits,
+    })
+}
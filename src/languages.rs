@@ -1,4 +1,14 @@
-use std::{collections::HashSet, fs::File, ops::Index, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::File,
+    ops::Index,
+    path::Path,
+    slice,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::{RegexSet, RegexSetBuilder};
 
 use serde::Deserialize;
 use thiserror::Error;
@@ -16,14 +26,90 @@ pub enum LanguagesError {
 
     #[error("extension \"{0}\" used twice")]
     ExtensionUsedTwice(String),
+
+    #[error("interpreter \"{0}\" used twice")]
+    InterpreterUsedTwice(String),
+
+    #[error("filename \"{0}\" has the wrong format")]
+    WrongFilenameFormat(String),
+
+    #[error("filename \"{0}\" used twice")]
+    FilenameUsedTwice(String),
+
+    #[error("glob \"{0}\" used twice")]
+    GlobUsedTwice(String),
+
+    #[error("invalid glob")]
+    Glob(#[from] globset::Error),
+
+    #[error("invalid heuristic")]
+    Regex(#[from] regex::Error),
 }
 
 #[derive(Deserialize)]
 pub struct Language {
     pub name: String,
     pub extensions: Box<[String]>,
+    // exact file names, take precedence over `globs` and `extensions`
+    pub filenames: Option<Box<[String]>>,
+    // matched against the file name, take precedence over `extensions`
+    pub globs: Option<Box<[String]>>,
+    // regexes matched against the first lines of a file to choose between languages
+    // sharing an extension, the language without heuristics (or the first one) is
+    // chosen if none match, `^` and `$` match at the start and end of each line
+    pub heuristics: Option<Box<[String]>>,
+    // matched against the shebang of files without an extension, ignoring version
+    // suffixes (e.g. "python" matches `#!/usr/bin/env python3.12`)
+    pub interpreters: Option<Box<[String]>>,
     pub line_comments: Option<Box<[String]>>,
     pub block_comments: Option<Box<[(String, String)]>>,
+    // whether `block_comments` and `doc_block_comments` can be nested
+    #[serde(default)]
+    pub nested: bool,
+    // block comments that can always be nested
+    pub nested_comments: Option<Box<[(String, String)]>>,
+    pub doc_line_comments: Option<Box<[String]>>,
+    pub doc_block_comments: Option<Box<[(String, String)]>>,
+    // a backslash escapes the next character
+    pub quotes: Option<Box<[Quote]>>,
+    // no escapes
+    pub verbatim_quotes: Option<Box<[Quote]>>,
+    // code following one of these attributes up to the end of the next `{ ... }` block
+    // or `;` is test code (e.g. `#[cfg(test)]`)
+    pub test_attributes: Option<Box<[String]>>,
+    // all code in files under a directory with one of these names is test code
+    pub test_dirs: Option<Box<[String]>>,
+}
+
+/// The delimiters of a string literal, written `[start, end]` in a language pack, or
+/// `[start, end, true]` if the literal can span several lines
+#[derive(Deserialize)]
+#[serde(from = "QuoteTokens")]
+pub struct Quote {
+    pub start: String,
+    pub end: String,
+    pub multiline: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QuoteTokens {
+    SingleLine(String, String),
+    Multiline(String, String, bool),
+}
+
+impl From<QuoteTokens> for Quote {
+    fn from(tokens: QuoteTokens) -> Self {
+        let (start, end, multiline) = match tokens {
+            QuoteTokens::SingleLine(start, end) => (start, end, false),
+            QuoteTokens::Multiline(start, end, multiline) => (start, end, multiline),
+        };
+        Self {
+            start,
+            end,
+            multiline,
+        }
+    }
 }
 
 pub type LanguageId = usize;
@@ -32,6 +118,11 @@ pub type LanguageId = usize;
 // Each language has an id equivalent to its position in the slice
 pub struct Languages {
     languages: Box<[Language]>,
+    extensions: HashMap<String, Box<[LanguageId]>>, // several if ambiguous, in pack order
+    filenames: HashMap<String, LanguageId>,
+    globs: GlobSet,
+    glob_languages: Box<[LanguageId]>, // the language of each glob in `globs`
+    heuristics: Box<[Option<RegexSet>]>, // by language id
 }
 
 impl Languages {
@@ -47,8 +138,8 @@ impl Languages {
     }
 
     pub fn from(languages: Box<[Language]>) -> Result<Languages, LanguagesError> {
-        let mut extensions = HashSet::new();
-        for lang in &languages {
+        let mut extensions = HashMap::<String, Vec<LanguageId>>::new();
+        for (lang_id, lang) in languages.iter().enumerate() {
             for ext in &lang.extensions {
                 if ext.chars().count() < 2 {
                     return Err(LanguagesError::WrongFormat("extension empty"));
@@ -60,22 +151,186 @@ impl Languages {
                     ));
                 }
 
-                if ext.chars().skip(1).any(|c| c == '.') {
-                    return Err(LanguagesError::WrongFormat("extension contains a dot"));
+                if ext.ends_with('.') || ext.contains("..") {
+                    return Err(LanguagesError::WrongFormat(
+                        "extension contains an empty part",
+                    ));
                 }
 
-                if !extensions.contains(&ext) {
-                    extensions.insert(ext);
-                } else {
-                    return Err(LanguagesError::ExtensionUsedTwice(ext.to_string()));
+                extensions.entry(ext.clone()).or_default().push(lang_id);
+            }
+        }
+
+        // an extension can only be shared if all but one of the languages using it
+        // have heuristics to tell them apart
+        for (ext, lang_ids) in &extensions {
+            let without_heuristics = lang_ids
+                .iter()
+                .filter(|lang_id| languages[**lang_id].heuristics.is_none())
+                .count();
+            if lang_ids.len() > 1 && without_heuristics > 1 {
+                return Err(LanguagesError::ExtensionUsedTwice(ext.to_string()));
+            }
+        }
+
+        let mut interpreters = HashSet::new();
+        for lang in &languages {
+            for interpreter in lang.interpreters.iter().flatten() {
+                if !interpreters.insert(interpreter) {
+                    return Err(LanguagesError::InterpreterUsedTwice(
+                        interpreter.to_string(),
+                    ));
+                }
+            }
+        }
+
+        let mut filenames = HashMap::new();
+        for (lang_id, lang) in languages.iter().enumerate() {
+            for filename in lang.filenames.iter().flatten() {
+                if filename.is_empty() || filename.contains('/') {
+                    return Err(LanguagesError::WrongFilenameFormat(filename.to_string()));
+                }
+
+                if filenames.insert(filename.clone(), lang_id).is_some() {
+                    return Err(LanguagesError::FilenameUsedTwice(filename.to_string()));
                 }
             }
         }
 
-        Ok(Languages { languages })
+        let mut glob_strs = HashSet::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut glob_languages = Vec::new();
+        for (lang_id, lang) in languages.iter().enumerate() {
+            for glob in lang.globs.iter().flatten() {
+                if !glob_strs.insert(glob) {
+                    return Err(LanguagesError::GlobUsedTwice(glob.to_string()));
+                }
+
+                globs.add(Glob::new(glob)?);
+                glob_languages.push(lang_id);
+            }
+        }
+        let globs = globs.build()?;
+
+        let heuristics = languages
+            .iter()
+            .map(|lang| {
+                lang.heuristics
+                    .as_deref()
+                    .map(|heuristics| RegexSetBuilder::new(heuristics).multi_line(true).build())
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Languages {
+            extensions: extensions
+                .into_iter()
+                .map(|(ext, lang_ids)| (ext, lang_ids.into()))
+                .collect(),
+            languages,
+            filenames,
+            globs,
+            glob_languages: glob_languages.into(),
+            heuristics,
+        })
+    }
+
+    // the candidate languages of a file given its name, by exact file name, then glob,
+    // then extension, several candidates need to be told apart with `disambiguate`
+    pub fn find(&self, file_name: &OsStr) -> &[LanguageId] {
+        let by_filename = || {
+            let lang_id = self.filenames.get(file_name.to_str()?)?;
+            Some(slice::from_ref(lang_id))
+        };
+
+        // globs are matched in pack order
+        let by_glob = || {
+            let i = self.globs.matches(file_name).into_iter().min()?;
+            Some(slice::from_ref(&self.glob_languages[i]))
+        };
+
+        let by_extension = || {
+            // `as_encoded_bytes` returns a "self-synchronizing superset of UTF-8"
+            // This means that everything after an ASCII dot can be checked for the
+            // extension it may be
+            let file_name = file_name.as_encoded_bytes();
+            // the longest matching extension wins, e.g. `.d.ts` over `.ts`, the dot at the
+            // start of a dotfile (e.g. `.ts`) doesn't start an extension
+            (1..file_name.len())
+                .filter(|i| file_name[*i] == b'.')
+                .find_map(|i| {
+                    let ext = std::str::from_utf8(&file_name[i..]).ok()?;
+                    self.extensions.get(ext)
+                })
+                .map(|lang_ids| &lang_ids[..])
+        };
+
+        by_filename()
+            .or_else(by_glob)
+            .or_else(by_extension)
+            .unwrap_or(&[])
+    }
+
+    // choose between the candidates returned by `find` given the first lines of the file
+    pub fn disambiguate(&self, candidates: &[LanguageId], head: &str) -> LanguageId {
+        let matching = candidates.iter().find(|lang_id| {
+            self.heuristics[**lang_id]
+                .as_ref()
+                .is_some_and(|heuristics| heuristics.is_match(head))
+        });
+        let fallback = || {
+            candidates
+                .iter()
+                .find(|lang_id| self.heuristics[**lang_id].is_none())
+        };
+
+        *matching
+            .or_else(fallback)
+            .or(candidates.first())
+            .expect("no candidates")
+    }
+
+    // case-insensitive, as in linguist overrides
+    pub fn find_by_name(&self, name: &str) -> Option<LanguageId> {
+        self.languages
+            .iter()
+            .position(|lang| lang.name.eq_ignore_ascii_case(name))
+    }
+
+    // the language of a script given its first line
+    pub fn find_by_shebang(&self, line: &str) -> Option<LanguageId> {
+        let interpreter = shebang_interpreter(line)?;
+        // try the exact name first, then without the version (`python3.12` -> `python`)
+        let unversioned = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+        [interpreter, unversioned].into_iter().find_map(|name| {
+            self.languages.iter().position(|lang| {
+                lang.interpreters
+                    .iter()
+                    .flatten()
+                    .any(|interpreter| interpreter == name)
+            })
+        })
     }
 }
 
+// the name of the interpreter in a shebang line, looking through `env`
+// e.g. `#!/bin/bash`, `#!/usr/bin/env python3`, `#!/usr/bin/env -S deno run`
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program != "env" {
+        return Some(program);
+    }
+
+    // skip options (including `-S`) and variable assignments
+    let program = words
+        .find(|word| !word.starts_with('-') && !word.contains('='))?
+        .rsplit('/')
+        .next()?;
+    Some(program)
+}
+
 impl Index<LanguageId> for Languages {
     type Output = Language;
 
@@ -93,3 +348,91 @@ impl<'a> IntoIterator for &'a Languages {
         (&self.languages).into_iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(languages: &Languages, lang_id: Option<LanguageId>) -> Option<&str> {
+        lang_id.map(|lang_id| languages[lang_id].name.as_str())
+    }
+
+    #[test]
+    fn longest_extension() {
+        let languages = Languages::builtin();
+        let find = |file_name: &str| {
+            languages
+                .find(OsStr::new(file_name))
+                .iter()
+                .map(|lang_id| languages[*lang_id].name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("index.d.ts"), ["TypeScript Declaration"]);
+        assert_eq!(find("index.test.d.ts"), ["TypeScript Declaration"]);
+        assert_eq!(find("index.ts"), ["TypeScript", "Qt Translation"]);
+        assert_eq!(find("main.old.rs"), ["Rust"]);
+        assert!(find(".ts").is_empty());
+        assert_eq!(find(".d.ts"), ["TypeScript", "Qt Translation"]);
+        assert!(find("rs").is_empty());
+    }
+
+    #[test]
+    fn disambiguate_ts() {
+        let languages = Languages::builtin();
+        let candidates = languages.find(OsStr::new("index.ts"));
+        let disambiguate = |head| {
+            languages[languages.disambiguate(candidates, head)]
+                .name
+                .as_str()
+        };
+        assert_eq!(
+            disambiguate("function f<TS extends string>(x: TS) {}"),
+            "TypeScript"
+        );
+        assert_eq!(
+            disambiguate("const x = 1;\ntype T = Map<TS, string>;"),
+            "TypeScript"
+        );
+        assert_eq!(
+            disambiguate("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE TS>"),
+            "Qt Translation"
+        );
+        assert_eq!(
+            disambiguate("<TS version=\"2.1\" language=\"de\">"),
+            "Qt Translation"
+        );
+    }
+
+    #[test]
+    fn shebang_interpreters() {
+        assert_eq!(shebang_interpreter("#!/bin/bash"), Some("bash"));
+        assert_eq!(shebang_interpreter("#! /bin/sh -e"), Some("sh"));
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env python3.12"),
+            Some("python3.12")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env -S deno run"),
+            Some("deno")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env VAR=1 node"),
+            Some("node")
+        );
+        assert_eq!(shebang_interpreter("#!/usr/bin/env"), None);
+        assert_eq!(shebang_interpreter("# not a shebang"), None);
+        assert_eq!(shebang_interpreter("fn main() {}"), None);
+    }
+
+    #[test]
+    fn find_by_shebang() {
+        let languages = Languages::builtin();
+        let find = |line| name(&languages, languages.find_by_shebang(line));
+        assert_eq!(find("#!/bin/bash"), Some("Shell"));
+        assert_eq!(find("#!/usr/bin/env python3.12"), Some("Python"));
+        assert_eq!(find("#!/usr/bin/env -S deno run"), Some("TypeScript"));
+        assert_eq!(find("#!/usr/bin/env VAR=1 node"), Some("JavaScript"));
+        assert_eq!(find("#!/usr/bin/env unknown"), None);
+        assert_eq!(find("print('hello')"), None);
+    }
+}
@@ -1,6 +1,17 @@
+mod authors;
+mod cargo;
 mod count;
+mod csv;
+mod diff;
+mod generated;
+mod git;
+mod gitattributes;
+mod history;
+mod json;
 mod languages;
+mod scanner;
 mod table;
+mod tree;
 mod util;
 
 use std::{
@@ -12,11 +23,24 @@ use std::{
 };
 
 use argh::{FromArgValue, FromArgs};
-use count::{Config, CountError, OutputCounts, run_count};
+use authors::make_author_counts;
+use cargo::{CargoError, Workspace, make_crate_counts, run_deps_count};
+use count::{Config, CountError, OutputCounts, run_count, run_index_count, run_rev_count};
+use csv::{make_csv, make_diff_csv, make_history_csv};
+use diff::{DiffOutput, run_changed_diff, run_diff};
+use git::{ChangeTarget, GitError, changed_files};
 use globset::{Glob, GlobSetBuilder};
+use history::{HistoryOutput, run_history};
+use json::{make_diff_json, make_history_json, make_json};
 use languages::{Languages, LanguagesError};
-use table::make_table;
+use serde::Serialize;
+use table::{
+    make_author_table, make_crate_table, make_dep_table, make_diff_table, make_dir_table,
+    make_excluded_table, make_file_diff_table, make_file_table, make_history_table, make_table,
+    make_unmatched_table,
+};
 use thiserror::Error;
+use tree::make_tree;
 
 // === Commands ===
 
@@ -29,7 +53,7 @@ struct Cargo {
     countlines: Countlines,
 }
 
-#[derive(FromArgs, PartialEq, Debug)]
+#[derive(FromArgs, PartialEq, Debug, Clone)]
 /// The actual `countlines` command
 #[argh(subcommand, name = "countlines")]
 struct Countlines {
@@ -53,6 +77,30 @@ struct Countlines {
     )]
     ignore_hidden: bool,
 
+    #[argh(
+        switch,
+        description = "don't respect .gitignore, .ignore, .git/info/exclude, or the global git excludes file, and walk .git directories"
+    )]
+    no_ignore: bool,
+
+    #[argh(
+        switch,
+        description = "don't apply the linguist-language, linguist-vendored, linguist-generated, or linguist-documentation attributes from .gitattributes files"
+    )]
+    no_linguist: bool,
+
+    #[argh(
+        switch,
+        description = "count generated files (lockfiles, protobuf outputs, minified files, or files marked as generated) like any other file instead of separately"
+    )]
+    include_generated: bool,
+
+    #[argh(
+        switch,
+        description = "add the counts of the files excluded as generated, vendored, or documentation to csv or tsv output as their own section, tables always show them"
+    )]
+    excluded: bool,
+
     #[argh(
         switch,
         short = 'q',
@@ -77,6 +125,88 @@ struct Countlines {
     )]
     machine_readable: bool,
 
+    #[argh(
+        option,
+        short = 'f',
+        description = "output format, possible values are `table` (default), `json`, `csv`, or `tsv`"
+    )]
+    format: Option<Format>,
+
+    #[argh(switch, description = "don't add a total row to csv or tsv output")]
+    no_total: bool,
+
+    #[argh(
+        switch,
+        description = "also report counts for each file, sorted by lines of code"
+    )]
+    by_file: bool,
+
+    #[argh(
+        option,
+        description = "also report counts for each directory, down to the given depth"
+    )]
+    by_dir: Option<usize>,
+
+    #[argh(
+        switch,
+        description = "break down the counts of each directory by language, requires --by-dir"
+    )]
+    dir_languages: bool,
+
+    #[argh(
+        switch,
+        description = "also report counts for each crate of the cargo workspace and each of its targets, counts the whole workspace if no path is given"
+    )]
+    by_crate: bool,
+
+    #[argh(
+        switch,
+        description = "count the sources of the dependencies of the current cargo package instead, they must already be downloaded or vendored"
+    )]
+    deps: bool,
+
+    #[argh(
+        option,
+        description = "the kinds of dependencies to count with --deps, possible values are `normal`, `dev`, or `build` (default all)"
+    )]
+    dep_kind: Vec<DepKind>,
+
+    #[argh(
+        switch,
+        description = "also report the files that didn't match any language, grouped by extension (or file name if they have none)"
+    )]
+    unmatched: bool,
+
+    #[argh(
+        option,
+        description = "count the files of a git revision (commit, tag, or branch) instead of the working directory, without checking it out"
+    )]
+    rev: Option<String>,
+
+    #[argh(
+        option,
+        description = "only count the files that differ from a git revision, in the working directory (including untracked files) or in --rev"
+    )]
+    changed_since: Option<String>,
+
+    #[argh(
+        switch,
+        description = "only count the files with staged changes, relative to HEAD or to --changed-since, as they are in the index"
+    )]
+    staged: bool,
+
+    #[argh(
+        switch,
+        description = "with --changed-since or --staged, also count the old version of each changed file and compare the two, file by file with --by-file"
+    )]
+    with_old: bool,
+
+    #[argh(
+        switch,
+        description = "also report the lines last changed by each author in each language, according to git blame"
+    )]
+    by_author: bool,
+
     #[argh(
         option,
         short = 'm',
@@ -84,11 +214,83 @@ struct Countlines {
     )]
     mode: Option<Mode>,
 
+    #[argh(
+        option,
+        description = "how to count lines containing both code and comments, possible values are `code` (default), `both`, or `separate`"
+    )]
+    mixed: Option<MixedPolicy>,
+
     #[argh(option, short = 'L', description = "use a custom language pack")]
     language_pack: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+#[argh(subcommand)]
+enum Command {
+    Diff(Diff),
+    History(History),
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// Compare the counts of two directories or git revisions, language by language
+#[argh(subcommand, name = "diff")]
+struct Diff {
+    #[argh(
+        positional,
+        description = "the old side, a directory, or a git revision of the analyzed path"
+    )]
+    old: String,
+
+    #[argh(
+        positional,
+        description = "the new side, a directory, or a git revision of the analyzed path"
+    )]
+    new: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// Count the analyzed path at past commits of the current branch, or of --rev,
+/// every commit by default
+#[argh(subcommand, name = "history")]
+struct History {
+    #[argh(
+        option,
+        description = "only count every Nth commit, always including the latest one"
+    )]
+    every: Option<usize>,
+
+    #[argh(
+        option,
+        description = "only count the last commit of each `day`, `week`, or `month` (in UTC)"
+    )]
+    per: Option<Period>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Period {
+    Day,
+    Week, // starting on Monday
+    Month,
+}
+
+impl FromArgValue for Period {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            p => Err(format!(
+                "invalid period `{p}`, expected `day`, `week`, or `month`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum Mode {
     Sync,
     Async,
@@ -108,12 +310,84 @@ impl FromArgValue for Mode {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum MixedPolicy {
+    Code,     // count as code
+    Both,     // count as code and as comment
+    Separate, // count in a separate `mixed` column
+}
+
+impl FromArgValue for MixedPolicy {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value {
+            "code" => Ok(Self::Code),
+            "both" => Ok(Self::Both),
+            "separate" => Ok(Self::Separate),
+            m => Err(format!(
+                "invalid mixed line policy `{m}`, expected `code`, `both`, or `separate`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+    Tsv,
+}
+
+impl FromArgValue for Format {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            f => Err(format!(
+                "invalid format `{f}`, expected `table`, `json`, `csv`, or `tsv`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DepKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+impl FromArgValue for DepKind {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value {
+            "normal" => Ok(Self::Normal),
+            "dev" => Ok(Self::Dev),
+            "build" => Ok(Self::Build),
+            k => Err(format!(
+                "invalid dependency kind `{k}`, expected `normal`, `dev`, or `build`"
+            )),
+        }
+    }
+}
+
 // === Errors ===
 
 #[derive(Error, Debug)]
 enum ArgumentError {
     #[error("specified path does not exist: {0}")]
     NonexistentPath(String),
+
+    #[error("{0} and {1} can't be used together")]
+    Incompatible(&'static str, &'static str),
+
+    #[error("{0} must be at least 1")]
+    Zero(&'static str),
+
+    #[error("{0} requires {1}")]
+    Requires(&'static str, &'static str),
 }
 
 #[derive(Error, Debug)]
@@ -132,6 +406,15 @@ enum AppError {
 
     #[error("count error")]
     CountError(#[from] CountError),
+
+    #[error("serde json error")]
+    SerdeJson(#[from] serde_json::Error),
+
+    #[error("cargo error")]
+    CargoError(#[from] CargoError),
+
+    #[error("git error")]
+    GitError(#[from] GitError),
 }
 
 // === Main ===
@@ -157,7 +440,32 @@ fn add_rel_dot(path: Cow<Path>) -> Cow<Path> {
 }
 
 fn parse_args(args: &Countlines) -> Result<Config, AppError> {
-    let (abs_root, rel_root) = match &args.path {
+    if args.deps && args.rev.is_some() {
+        return Err(ArgumentError::Incompatible("--deps", "--rev").into());
+    }
+    if args.deps && (args.changed_since.is_some() || args.staged) {
+        return Err(ArgumentError::Incompatible("--deps", "--changed-since/--staged").into());
+    }
+    if args.staged && args.rev.is_some() {
+        return Err(ArgumentError::Incompatible("--staged", "--rev").into());
+    }
+    if args.dir_languages && args.by_dir.is_none() {
+        return Err(ArgumentError::Requires("--dir-languages", "--by-dir").into());
+    }
+    if args.with_old && args.changed_since.is_none() && !args.staged {
+        return Err(ArgumentError::Requires("--with-old", "--changed-since or --staged").into());
+    }
+    if args.by_author && args.deps {
+        return Err(ArgumentError::Incompatible("--by-author", "--deps").into());
+    }
+    if args.by_author && args.staged {
+        return Err(ArgumentError::Incompatible("--by-author", "--staged").into());
+    }
+    if args.by_author && args.with_old {
+        return Err(ArgumentError::Incompatible("--by-author", "--with-old").into());
+    }
+
+    let (mut abs_root, mut rel_root) = match &args.path {
         Some(path) => {
             let input_root = PathBuf::from(&path);
             if !input_root.exists() {
@@ -179,6 +487,36 @@ fn parse_args(args: &Countlines) -> Result<Config, AppError> {
         }
     };
 
+    // revisions and changes are listed by git for a whole directory
+    if args.rev.is_some() && !abs_root.is_dir() {
+        return Err(ArgumentError::Requires("--rev", "a directory path").into());
+    }
+    if (args.changed_since.is_some() || args.staged) && !abs_root.is_dir() {
+        return Err(ArgumentError::Requires("--changed-since/--staged", "a directory path").into());
+    }
+
+    if args.by_author {
+        let dir = if abs_root.is_dir() {
+            &abs_root
+        } else {
+            abs_root.parent().unwrap_or(&abs_root)
+        };
+        if !git::is_work_tree(dir)? {
+            return Err(ArgumentError::Requires("--by-author", "a git work tree").into());
+        }
+    }
+
+    let workspace = if args.by_crate {
+        let workspace = Workspace::load(&abs_root)?;
+        if args.path.is_none() {
+            abs_root = workspace.root.clone();
+            rel_root = relativize_path((&abs_root).into()).into_owned();
+        }
+        Some(workspace)
+    } else {
+        None
+    };
+
     let languages = if let Some(lang_pack) = &args.language_pack {
         Languages::load(Path::new(lang_pack))?
     } else {
@@ -203,28 +541,145 @@ fn parse_args(args: &Countlines) -> Result<Config, AppError> {
     }
     let exclude = builder.build()?;
 
+    let changed = if args.changed_since.is_some() || args.staged {
+        let base = args.changed_since.as_deref().unwrap_or("HEAD");
+        let target = match &args.rev {
+            Some(rev) => ChangeTarget::Rev(rev.clone()),
+            None if args.staged => ChangeTarget::Index,
+            None => ChangeTarget::WorkTree,
+        };
+        Some(changed_files(&abs_root, base, target)?)
+    } else {
+        None
+    };
+
     Ok(Config {
         abs_root,
         rel_root,
         languages,
         exclude,
         ignore_hidden: args.ignore_hidden,
+        no_ignore: args.no_ignore,
+        no_linguist: args.no_linguist,
+        include_generated: args.include_generated,
+        excluded: args.excluded,
+        unmatched: args.unmatched,
+        rev: args.rev.clone(),
+        changed,
+        with_old: args.with_old,
         quiet: args.quiet,
         max_depth: args.max_depth,
         follow_links: args.follow_links,
         machine_readable: args.machine_readable,
+        format: args.format.unwrap_or(Format::Table),
+        no_total: args.no_total,
+        by_file: args.by_file,
+        by_dir: args.by_dir,
+        dir_languages: args.dir_languages,
+        workspace,
+        by_author: args.by_author,
+        deps: args.deps.then(|| {
+            if args.dep_kind.is_empty() {
+                vec![DepKind::Normal, DepKind::Dev, DepKind::Build]
+            } else {
+                args.dep_kind.clone()
+            }
+        }),
         mode: args.mode.unwrap_or(Mode::Parallel),
+        mixed: args.mixed.unwrap_or(MixedPolicy::Code),
     })
 }
 
-fn print(output: OutputCounts, config: &Config, time: Duration) {
-    let table = make_table(&output, &config);
-    println!("{table}");
+fn print(output: OutputCounts, config: &Config, time: Duration) -> Result<(), AppError> {
+    match config.format {
+        Format::Table => {
+            let table = make_table(&output, &config);
+            println!("{table}");
+
+            if !output.excluded.is_empty() {
+                let table = make_excluded_table(&output, config);
+                println!("{table}");
+            }
+
+            if config.by_file {
+                let table = make_file_table(&output, config);
+                println!("{table}");
+            }
+
+            if let Some(max_depth) = config.by_dir {
+                let table = make_dir_table(&make_tree(&output, config, max_depth), config);
+                println!("{table}");
+            }
+
+            if let Some(workspace) = &config.workspace {
+                let crates = make_crate_counts(&output, workspace, config);
+                let table = make_crate_table(&crates, config);
+                println!("{table}");
+            }
+
+            if config.deps.is_some() {
+                let table = make_dep_table(&output, config);
+                println!("{table}");
+            }
+
+            if config.unmatched {
+                let table = make_unmatched_table(&output, config);
+                println!("{table}");
+            }
+
+            if config.by_author {
+                let table = make_author_table(&output, config);
+                println!("{table}");
+            }
+
+            if !config.machine_readable {
+                println!("{} files unmatched", output.unmatched_files);
+                println!("{} files errored", output.error_files);
+                println!("results in {:?}", time);
+            }
+        }
+        Format::Json => println!("{}", make_json(&output, config, time)?),
+        Format::Csv => print!("{}", make_csv(&output, config, ',')),
+        Format::Tsv => print!("{}", make_csv(&output, config, '\t')),
+    }
+
+    Ok(())
+}
+
+fn print_diff(diff: &DiffOutput, config: &Config) -> Result<(), AppError> {
+    match config.format {
+        Format::Table => {
+            println!("{}", make_diff_table(diff, config));
+            if let Some(files) = &diff.files {
+                println!("{}", make_file_diff_table(files, config));
+            }
+            if !config.machine_readable {
+                println!("from {} to {}", diff.old_name, diff.new_name);
+                println!("results in {:?}", diff.time);
+            }
+        }
+        Format::Json => println!("{}", make_diff_json(diff, config)?),
+        Format::Csv => print!("{}", make_diff_csv(diff, config, ',')),
+        Format::Tsv => print!("{}", make_diff_csv(diff, config, '\t')),
+    }
+
+    Ok(())
+}
 
-    if !config.machine_readable {
-        println!("{} files errored", output.error_files);
-        println!("results in {:?}", time);
+fn print_history(history: &HistoryOutput, config: &Config) -> Result<(), AppError> {
+    match config.format {
+        Format::Table => {
+            println!("{}", make_history_table(history, config));
+            if !config.machine_readable {
+                println!("results in {:?}", history.time);
+            }
+        }
+        Format::Json => println!("{}", make_history_json(history, config)?),
+        Format::Csv => print!("{}", make_history_csv(history, config, ',')),
+        Format::Tsv => print!("{}", make_history_csv(history, config, '\t')),
     }
+
+    Ok(())
 }
 
 fn main_() -> Result<(), AppError> {
@@ -232,13 +687,38 @@ fn main_() -> Result<(), AppError> {
 
     let Cargo { countlines: args } = argh::from_env();
 
+    match &args.command {
+        Some(Command::Diff(diff)) => {
+            let (diff, config) = run_diff(&args, diff)?;
+            return print_diff(&diff, &config);
+        }
+        Some(Command::History(history)) => {
+            let (history, config) = run_history(&args, history)?;
+            return print_history(&history, &config);
+        }
+        None => {}
+    }
+
     let config = parse_args(&args)?;
 
+    if config.with_old {
+        let diff = run_changed_diff(&config)?;
+        return print_diff(&diff, &config);
+    }
+
     let start = Instant::now();
-    let output = run_count(&config)?;
+    let mut output = match (&config.deps, &config.rev) {
+        (Some(kinds), _) => run_deps_count(&config, kinds)?,
+        (None, Some(rev)) => run_rev_count(&config, rev)?,
+        (None, None) if config.counts_index() => run_index_count(&config)?,
+        (None, None) => run_count(&config)?,
+    };
+    if config.by_author {
+        output.authors = make_author_counts(&output, &config)?;
+    }
     let time = start.elapsed();
 
-    print(output, &config, time);
+    print(output, &config, time)?;
 
     Ok(())
 }
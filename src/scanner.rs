@@ -0,0 +1,428 @@
+use crate::languages::{Language, Quote};
+
+/// What was found on a single line
+#[derive(Default, Clone, Copy)]
+pub struct Line {
+    pub code: bool,
+    pub comment: bool,
+    pub doc: bool,
+}
+
+impl Line {
+    fn mark_comment(&mut self, doc: bool) {
+        if doc {
+            self.doc = true;
+        } else {
+            self.comment = true;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State<'a> {
+    Normal,
+    BlockComment {
+        start: &'a str, // the token of nested comments, the plain one in doc comments
+        end: &'a str,
+        nested: bool,
+        doc: bool,
+        depth: usize,
+    },
+    Quote {
+        end: &'a str,
+        escapes: bool,
+        multiline: bool, // otherwise the literal ends at the end of the line
+    },
+}
+
+fn as_slice<T>(tokens: &Option<Box<[T]>>) -> &[T] {
+    tokens.as_ref().map(|t| t.as_ref()).unwrap_or(&[])
+}
+
+// returns the longest token that `text` starts with
+fn longest_match<'a, T>(
+    text: &str,
+    tokens: impl IntoIterator<Item = &'a T>,
+    start: impl Fn(&'a T) -> &'a str,
+) -> Option<&'a T> {
+    tokens
+        .into_iter()
+        .filter(|t| text.starts_with(start(t)))
+        .max_by_key(|t| start(t).len())
+}
+
+/// Scans a file line by line, keeping track of block comments and string literals
+/// that span multiple lines
+pub struct LineScanner<'a> {
+    line_comments: &'a [String],
+    block_comments: &'a [(String, String)],
+    nested: bool,
+    nested_comments: &'a [(String, String)],
+    doc_line_comments: &'a [String],
+    doc_block_comments: &'a [(String, String)],
+    quotes: &'a [Quote],
+    verbatim_quotes: &'a [Quote],
+    // whether `'` starts character literals instead of string literals
+    char_literals: bool,
+    state: State<'a>,
+    // the code on the last scanned line, excluding comments and string literals,
+    // only collected if the language has test attributes to look for
+    collect_code: bool,
+    code: String,
+}
+
+impl<'a> LineScanner<'a> {
+    pub fn new(lang: &'a Language) -> Self {
+        Self {
+            line_comments: as_slice(&lang.line_comments),
+            block_comments: as_slice(&lang.block_comments),
+            nested: lang.nested,
+            nested_comments: as_slice(&lang.nested_comments),
+            doc_line_comments: as_slice(&lang.doc_line_comments),
+            doc_block_comments: as_slice(&lang.doc_block_comments),
+            quotes: as_slice(&lang.quotes),
+            verbatim_quotes: as_slice(&lang.verbatim_quotes),
+            char_literals: !as_slice(&lang.quotes)
+                .iter()
+                .chain(as_slice(&lang.verbatim_quotes))
+                .any(|quote| quote.start.starts_with('\'')),
+            state: State::Normal,
+            collect_code: lang.test_attributes.is_some(),
+            code: String::new(),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    // the length of the character literal `text` starts with, if `'` doesn't start string
+    // literals (e.g. in rust), so that `'"'` doesn't open one
+    fn char_literal_len(&self, text: &str) -> Option<usize> {
+        if !self.char_literals {
+            return None;
+        }
+        let mut chars = text.strip_prefix('\'')?.chars();
+        let len = match chars.next()? {
+            '\\' => 2 + chars.next()?.len_utf8(),
+            c => 1 + c.len_utf8(),
+        };
+        text[len..].starts_with('\'').then_some(len + 1)
+    }
+
+    // whether `text` opens a doc comment with the token `start`, a doc token made of a
+    // plain one and a marker doesn't when the marker is repeated (e.g. `////` or `/***`)
+    // or is part of the closing token (e.g. `/**/`)
+    fn is_doc_token(&self, text: &str, start: &str, end: Option<&str>) -> bool {
+        if !text.starts_with(start) {
+            return false;
+        }
+        let plain = self
+            .line_comments
+            .iter()
+            .chain(
+                self.block_comments
+                    .iter()
+                    .chain(self.nested_comments)
+                    .map(|(start, _)| start),
+            )
+            .filter(|plain| plain.len() < start.len() && start.starts_with(plain.as_str()))
+            .max_by_key(|plain| plain.len());
+        let Some(plain) = plain else {
+            return true;
+        };
+        let Some(marker) = start[plain.len()..].chars().last() else {
+            return true;
+        };
+        !text[start.len()..].starts_with(marker)
+            && !end.is_some_and(|end| text[plain.len()..].starts_with(end))
+    }
+
+    pub fn scan(&mut self, line: &str) -> Line {
+        let mut result = Line::default();
+        let mut rest = line.trim();
+        self.code.clear();
+
+        while let Some(c) = rest.chars().next() {
+            match self.state {
+                State::Normal => {}
+                State::BlockComment {
+                    start,
+                    end,
+                    nested,
+                    doc,
+                    depth,
+                } => {
+                    result.mark_comment(doc);
+                    let next_start = if nested { rest.find(start) } else { None };
+                    match (next_start, rest.find(end)) {
+                        (Some(i), j) if j.is_none_or(|j| i < j) => {
+                            self.state = State::BlockComment {
+                                start,
+                                end,
+                                nested,
+                                doc,
+                                depth: depth + 1,
+                            };
+                            rest = &rest[i + start.len()..];
+                        }
+                        (_, Some(j)) => {
+                            self.state = if depth > 1 {
+                                State::BlockComment {
+                                    start,
+                                    end,
+                                    nested,
+                                    doc,
+                                    depth: depth - 1,
+                                }
+                            } else {
+                                State::Normal
+                            };
+                            rest = &rest[j + end.len()..];
+                        }
+                        (_, None) => break,
+                    }
+                    continue;
+                }
+                State::Quote { end, escapes, .. } => {
+                    // the contents of a string literal are code
+                    result.code = true;
+                    if escapes && c == '\\' {
+                        let mut chars = rest.chars();
+                        chars.next();
+                        chars.next();
+                        rest = chars.as_str();
+                    } else if rest.starts_with(end) {
+                        self.state = State::Normal;
+                        rest = &rest[end.len()..];
+                    } else {
+                        rest = &rest[c.len_utf8()..];
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(len) = self.char_literal_len(rest) {
+                result.code = true;
+                if self.collect_code {
+                    self.code.push_str(&rest[..len]);
+                }
+                rest = &rest[len..];
+                continue;
+            }
+
+            // (token length, is doc)
+            let line_comment = longest_match(rest, self.line_comments, |lc| lc)
+                .map(|lc| (lc.len(), false))
+                .into_iter()
+                .chain(
+                    longest_match(
+                        rest,
+                        self.doc_line_comments
+                            .iter()
+                            .filter(|lc| self.is_doc_token(rest, lc, None)),
+                        |lc| lc,
+                    )
+                    .map(|lc| (lc.len(), true)),
+                )
+                .max_by_key(|(len, _)| *len);
+            // (tokens, is nested, is doc)
+            let block_comment = longest_match(rest, self.block_comments, |(start, _)| start)
+                .map(|bc| (bc, self.nested, false))
+                .into_iter()
+                .chain(
+                    longest_match(rest, self.nested_comments, |(start, _)| start)
+                        .map(|bc| (bc, true, false)),
+                )
+                .chain(
+                    longest_match(
+                        rest,
+                        self.doc_block_comments
+                            .iter()
+                            .filter(|(start, end)| self.is_doc_token(rest, start, Some(end))),
+                        |(start, _)| start,
+                    )
+                    .map(|bc| (bc, self.nested, true)),
+                )
+                .max_by_key(|((start, _), _, _)| start.len());
+            let comment_len = line_comment
+                .map(|(len, _)| len)
+                .into_iter()
+                .chain(block_comment.map(|((start, _), _, _)| start.len()))
+                .max();
+
+            let quote = longest_match(rest, self.quotes, |q| &q.start)
+                .map(|q| (q, true))
+                .into_iter()
+                .chain(longest_match(rest, self.verbatim_quotes, |q| &q.start).map(|q| (q, false)))
+                .max_by_key(|(q, _)| q.start.len());
+
+            // a token that is both a comment and a quote (e.g. python's `"""`) opens a
+            // comment at the start of a line and a string literal after code
+            let quote = quote.filter(|(q, _)| match comment_len {
+                None => true,
+                Some(len) => q.start.len() > len || (q.start.len() == len && result.code),
+            });
+
+            if let Some((q, escapes)) = quote {
+                result.code = true;
+                self.state = State::Quote {
+                    end: &q.end,
+                    escapes,
+                    multiline: q.multiline,
+                };
+                rest = &rest[q.start.len()..];
+                continue;
+            }
+
+            match (line_comment, block_comment) {
+                (Some((lc_len, doc)), bc)
+                    if bc.is_none_or(|((start, _), _, _)| lc_len >= start.len()) =>
+                {
+                    result.mark_comment(doc);
+                    break;
+                }
+                (_, Some(((start, end), nested, doc))) => {
+                    result.mark_comment(doc);
+                    // comments nested in a doc comment are plain ones (e.g. `/*` in `/** */`)
+                    let nested_start = if doc {
+                        self.block_comments
+                            .iter()
+                            .chain(self.nested_comments)
+                            .find(|(_, plain_end)| plain_end == end)
+                            .map_or(start, |(plain_start, _)| plain_start)
+                    } else {
+                        start
+                    };
+                    self.state = State::BlockComment {
+                        start: nested_start,
+                        end,
+                        nested,
+                        doc,
+                        depth: 1,
+                    };
+                    rest = &rest[start.len()..];
+                    continue;
+                }
+                _ => {}
+            }
+
+            if !c.is_whitespace() {
+                result.code = true;
+            }
+            if self.collect_code {
+                self.code.push(c);
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+
+        // an unterminated single line literal (e.g. a stray quote) doesn't swallow the
+        // following lines
+        if let State::Quote {
+            multiline: false, ..
+        } = self.state
+        {
+            self.state = State::Normal;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages::Languages;
+
+    // the kind of each line, code takes precedence over doc and doc over comments
+    fn scan(lang: &str, text: &str) -> Vec<&'static str> {
+        let languages = Languages::builtin();
+        let lang = &languages[languages.find_by_name(lang).unwrap()];
+        let mut scanner = LineScanner::new(lang);
+        text.lines()
+            .map(|line| match scanner.scan(line) {
+                Line { code: true, .. } => "code",
+                Line { doc: true, .. } => "doc",
+                Line { comment: true, .. } => "comment",
+                _ => "blank",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn char_literal_quote() {
+        let text = "if c == '\"' {\n// a comment\n// another\n}";
+        assert_eq!(scan("Rust", text), ["code", "comment", "comment", "code"]);
+    }
+
+    #[test]
+    fn byte_and_escaped_char_literals() {
+        let text = "let a = b'\"';\n// a\nlet b = '\\'';\n// b\nlet c = '\\\"';\n// c";
+        assert_eq!(
+            scan("Rust", text),
+            ["code", "comment", "code", "comment", "code", "comment"]
+        );
+    }
+
+    #[test]
+    fn multiline_string() {
+        let text = "let s = \"a\n// not a comment\";\n// a comment";
+        assert_eq!(scan("Rust", text), ["code", "code", "comment"]);
+    }
+
+    #[test]
+    fn unterminated_quote_ends_at_end_of_line() {
+        let text = "let s = \"abc\n// c\nfoo();";
+        assert_eq!(scan("JavaScript", text), ["code", "comment", "code"]);
+    }
+
+    #[test]
+    fn apostrophe_in_heredoc() {
+        let text = "cat <<EOF\ndon't\nEOF\n# comment\necho \"$(ls) # don't\"\n# another";
+        assert_eq!(
+            scan("Shell", text),
+            ["code", "code", "code", "comment", "code", "comment"]
+        );
+    }
+
+    #[test]
+    fn matlab_transpose() {
+        let text = "x = A';\n% comment\ny = 1;";
+        assert_eq!(scan("MATLAB", text), ["code", "comment", "code"]);
+    }
+
+    #[test]
+    fn empty_block_comment() {
+        let text = "/**/\nfoo();\n// c\nbar();";
+        assert_eq!(
+            scan("JavaScript", text),
+            ["comment", "code", "comment", "code"]
+        );
+    }
+
+    #[test]
+    fn repeated_doc_marker() {
+        let text = "//// separator\n/// doc\n//! inner doc\nfoo();";
+        assert_eq!(scan("Rust", text), ["comment", "doc", "doc", "code"]);
+        let text = "/***/\n/*** banner */\n/** doc */\n/*!* inner doc */";
+        assert_eq!(scan("Rust", text), ["comment", "comment", "doc", "doc"]);
+        let text = "/*****\n * banner\n *****/\nint x;";
+        assert_eq!(scan("C", text), ["comment", "comment", "comment", "code"]);
+    }
+
+    #[test]
+    fn comment_nested_in_doc_comment() {
+        let text = "/** doc /* inner */ still doc */\nfoo();";
+        assert_eq!(scan("Rust", text), ["doc", "code"]);
+        let text = "/*! doc /* inner */ still doc */\nfoo();";
+        assert_eq!(scan("Rust", text), ["doc", "code"]);
+        let text = "{-| doc {- inner -} still doc -}\nfoo = 1";
+        assert_eq!(scan("Haskell", text), ["doc", "code"]);
+    }
+
+    #[test]
+    fn triple_quote_spans_lines() {
+        let text = "x = \"\"\"a\n# b\"\"\"\n# c";
+        assert_eq!(scan("Python", text), ["code", "code", "comment"]);
+    }
+}
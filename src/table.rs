@@ -1,4 +1,7 @@
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+};
 
 use tabled::{
     builder::Builder,
@@ -6,13 +9,19 @@ use tabled::{
 };
 
 use crate::{
-    count::{Config, Counts, OutputCounts},
-    util::format_number,
+    MixedPolicy,
+    authors::sort_authors,
+    cargo::{CrateCounts, format_kinds, sort_crates, sort_deps},
+    count::{Config, Counts, FileCounts, OutputCounts, UnmatchedCounts, display_path},
+    diff::{DiffOutput, FileDiff},
+    history::HistoryOutput,
+    languages::LanguageId,
+    tree::DirNode,
+    util::{format_date, format_delta, format_number},
 };
 
-fn sort_counts(output: &OutputCounts) -> Vec<(usize, &Counts)> {
-    let mut sorted_counts = output
-        .counts
+pub fn sort_counts(counts: &HashMap<LanguageId, Counts>) -> Vec<(LanguageId, &Counts)> {
+    let mut sorted_counts = counts
         .iter()
         .map(|(lang_id, counts)| (*lang_id, counts))
         .collect::<Vec<_>>();
@@ -27,71 +36,83 @@ fn sort_counts(output: &OutputCounts) -> Vec<(usize, &Counts)> {
     sorted_counts
 }
 
-pub fn make_table(output: &OutputCounts, config: &Config) -> String {
-    let sorted_counts = sort_counts(output);
+pub type Column = (&'static str, fn(&Counts) -> usize);
+
+// the count columns to show, depending on the configuration
+pub fn columns(config: &Config) -> Vec<Column> {
+    let mut columns: Vec<Column> = vec![
+        ("files", |c| c.files),
+        ("code", |c| c.code),
+        ("test", |c| c.test_code),
+        ("comment", |c| c.comment),
+        ("doc", |c| c.doc),
+    ];
+    if config.mixed == MixedPolicy::Separate {
+        columns.push(("mixed", |c| c.mixed));
+    }
+    columns.push(("blank", |c| c.blank));
+    columns.push(("invalid", |c| c.invalid));
+    columns
+}
+
+fn delta_cells<'a>(
+    columns: &'a [Column],
+    old: &'a Counts,
+    new: &'a Counts,
+) -> impl Iterator<Item = String> + 'a {
+    columns
+        .iter()
+        .map(|(_, get)| format_delta(get(old), get(new)))
+}
+
+fn count_cells<'a>(columns: &'a [Column], counts: &'a Counts) -> impl Iterator<Item = String> + 'a {
+    columns.iter().map(|(_, get)| format_number(get(counts)))
+}
 
+// `labels` is the number of leading text columns in each row, the rest are right-aligned
+fn render(
+    labels: usize,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    total: Option<Vec<String>>,
+    config: &Config,
+) -> String {
     let mut builder = Builder::default();
 
+    let num_rows = rows.len();
+    let total = total.filter(|_| !config.machine_readable);
+    let has_total = total.is_some();
+
     if !config.machine_readable {
-        builder.push_record(["", "files", "code", "comment", "blank", "invalid"]);
+        builder.push_record(header);
     }
-
-    for (lang_id, counts) in &sorted_counts {
-        builder.push_record([
-            config.languages[*lang_id].name.clone(),
-            format_number(counts.files),
-            format_number(counts.code),
-            format_number(counts.comment),
-            format_number(counts.blank),
-            format_number(counts.invalid),
-        ]);
+    for row in rows {
+        builder.push_record(row);
     }
-
-    if !config.machine_readable {
-        let mut total_files = 0;
-        let mut total_code = 0;
-        let mut total_comment = 0;
-        let mut total_blank = 0;
-        let mut total_invalid = 0;
-
-        for (_, counts) in &sorted_counts {
-            total_files += counts.files;
-            total_code += counts.code;
-            total_comment += counts.comment;
-            total_blank += counts.blank;
-            total_invalid += counts.invalid;
-        }
-
-        builder.push_record([
-            "Total".to_string(),
-            format_number(total_files),
-            format_number(total_code),
-            format_number(total_comment),
-            format_number(total_blank),
-            format_number(total_invalid),
-        ]);
+    if let Some(total) = total {
+        builder.push_record(total);
     }
 
     let mut table = builder.build();
 
     if config.machine_readable {
-        table.modify(Segment::new(.., 1..), Alignment::right());
+        table.modify(Segment::new(.., labels..), Alignment::right());
     } else {
-        table.modify(Segment::new(1.., 1..), Alignment::right());
+        table.modify(Segment::new(1.., labels..), Alignment::right());
     }
 
     if config.machine_readable {
         table.with(Style::empty());
     } else {
-        // if there are no files, don't add the second internal hline as it makes
+        // if there are no rows, don't add the second internal hline as it makes
         // the bottom of the table look wrong
-        if sorted_counts.is_empty() {
+        if num_rows == 0 || !has_total {
             table.with(Style::rounded());
         } else {
             table.with(Style::rounded().horizontals([
                 (1, HorizontalLine::inherit(Style::modern_rounded())),
                 (
-                    sorted_counts.len() + 1,
+                    num_rows + 1,
                     HorizontalLine::inherit(Style::modern_rounded()),
                 ),
             ]));
@@ -100,3 +121,379 @@ pub fn make_table(output: &OutputCounts, config: &Config) -> String {
 
     format!("{table}")
 }
+
+pub fn make_table(output: &OutputCounts, config: &Config) -> String {
+    let sorted_counts = sort_counts(&output.counts);
+    let columns = columns(config);
+
+    let header = std::iter::once("")
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = sorted_counts
+        .iter()
+        .map(|(lang_id, counts)| {
+            std::iter::once(config.languages[*lang_id].name.clone())
+                .chain(count_cells(&columns, counts))
+                .collect()
+        })
+        .collect();
+
+    let mut total = Counts::default();
+    for (_, counts) in &sorted_counts {
+        total.merge(counts);
+    }
+    let total = std::iter::once("Total".to_string())
+        .chain(count_cells(&columns, &total))
+        .collect();
+
+    render(1, header, rows, Some(total), config)
+}
+
+// vendored, generated, and documentation files
+pub fn make_excluded_table(output: &OutputCounts, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = std::iter::once("excluded")
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = output
+        .excluded
+        .iter()
+        .map(|(category, counts)| {
+            std::iter::once(category.name().to_string())
+                .chain(count_cells(&columns, counts))
+                .collect()
+        })
+        .collect();
+
+    render(1, header, rows, None, config)
+}
+
+pub fn sort_files(output: &OutputCounts) -> Vec<&FileCounts> {
+    let mut sorted_files = output.files.iter().collect::<Vec<_>>();
+
+    // reverse order by number of code lines, forward order by path
+    sorted_files.sort_unstable_by(
+        |file1, file2| match file2.counts.code.cmp(&file1.counts.code) {
+            Ordering::Equal => file1.path.cmp(&file2.path),
+            ord => ord,
+        },
+    );
+    sorted_files
+}
+
+pub fn make_file_table(output: &OutputCounts, config: &Config) -> String {
+    // skip the `files` column, it's always 1
+    let columns = &columns(config)[1..];
+
+    let header = ["file", "language"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = sort_files(output)
+        .into_iter()
+        .map(|file| {
+            [
+                display_path(&file.path, config),
+                config.languages[file.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(count_cells(columns, &file.counts))
+            .collect()
+        })
+        .collect();
+
+    render(2, header, rows, None, config)
+}
+
+fn push_dir_rows(
+    rows: &mut Vec<Vec<String>>,
+    name: String,
+    node: &DirNode,
+    depth: usize,
+    columns: &[Column],
+    config: &Config,
+) {
+    let indent = "  ".repeat(depth);
+
+    let mut row = vec![format!("{indent}{name}")];
+    if config.dir_languages {
+        row.push(String::new());
+    }
+    row.extend(count_cells(columns, &node.counts));
+    rows.push(row);
+
+    if config.dir_languages {
+        for (lang_id, counts) in sort_counts(&node.languages) {
+            rows.push(
+                [String::new(), config.languages[lang_id].name.clone()]
+                    .into_iter()
+                    .chain(count_cells(columns, counts))
+                    .collect(),
+            );
+        }
+    }
+
+    for (child_name, child) in node.sorted_children() {
+        push_dir_rows(
+            rows,
+            format!("{child_name}/"),
+            child,
+            depth + 1,
+            columns,
+            config,
+        );
+    }
+}
+
+pub fn make_dir_table(tree: &DirNode, config: &Config) -> String {
+    let columns = columns(config);
+
+    let labels: &[&str] = if config.dir_languages {
+        &["directory", "language"]
+    } else {
+        &["directory"]
+    };
+    let header = labels
+        .iter()
+        .copied()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let mut rows = Vec::new();
+    let root_name = config.rel_root.to_string_lossy().into_owned();
+    push_dir_rows(&mut rows, root_name, tree, 0, &columns, config);
+
+    render(labels.len(), header, rows, None, config)
+}
+
+pub fn make_crate_table(crates: &BTreeMap<String, CrateCounts>, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = ["crate", "target"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let mut rows = Vec::new();
+    for (name, crate_counts) in sort_crates(crates) {
+        rows.push(
+            [name.clone(), String::new()]
+                .into_iter()
+                .chain(count_cells(&columns, &crate_counts.counts))
+                .collect(),
+        );
+        for (kind, counts) in crate_counts.sorted_targets() {
+            rows.push(
+                [String::new(), kind.to_string()]
+                    .into_iter()
+                    .chain(count_cells(&columns, counts))
+                    .collect(),
+            );
+        }
+    }
+
+    render(2, header, rows, None, config)
+}
+
+pub fn make_dep_table(output: &OutputCounts, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = ["dependency", "kind"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = sort_deps(&output.deps)
+        .into_iter()
+        .map(|dep| {
+            [
+                format!("{} {}", dep.name, dep.version),
+                format_kinds(&dep.kinds),
+            ]
+            .into_iter()
+            .chain(count_cells(&columns, &dep.counts))
+            .collect()
+        })
+        .collect();
+
+    let mut total = Counts::default();
+    for dep in &output.deps {
+        total.merge(&dep.counts);
+    }
+    let total = ["Total".to_string(), String::new()]
+        .into_iter()
+        .chain(count_cells(&columns, &total))
+        .collect();
+
+    render(2, header, rows, Some(total), config)
+}
+
+pub fn make_author_table(output: &OutputCounts, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = ["author", "language"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = sort_authors(&output.authors)
+        .into_iter()
+        .map(|author| {
+            [
+                author.author.clone(),
+                config.languages[author.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(count_cells(&columns, &author.counts))
+            .collect()
+        })
+        .collect();
+
+    // files are counted once for each of their authors
+    let mut total = Counts::default();
+    for author in &output.authors {
+        total.merge(&author.counts);
+    }
+    total.files = output.files.len();
+    let total = ["Total".to_string(), String::new()]
+        .into_iter()
+        .chain(count_cells(&columns, &total))
+        .collect();
+
+    render(2, header, rows, Some(total), config)
+}
+
+// reverse order by number of files, forward order by extension
+pub fn sort_unmatched(output: &OutputCounts) -> Vec<(&String, &UnmatchedCounts)> {
+    let mut sorted_unmatched = output.unmatched.iter().collect::<Vec<_>>();
+    sorted_unmatched.sort_unstable_by(|(key1, counts1), (key2, counts2)| {
+        counts2
+            .files
+            .cmp(&counts1.files)
+            .then_with(|| key1.cmp(key2))
+    });
+    sorted_unmatched
+}
+
+pub fn make_unmatched_table(output: &OutputCounts, config: &Config) -> String {
+    let header = ["unmatched", "files", "bytes"]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    let rows = sort_unmatched(output)
+        .into_iter()
+        .map(|(key, counts)| {
+            vec![
+                key.clone(),
+                format_number(counts.files),
+                format_number(counts.bytes as usize),
+            ]
+        })
+        .collect();
+
+    let total = vec![
+        "Total".to_string(),
+        format_number(output.unmatched.values().map(|counts| counts.files).sum()),
+        format_number(
+            output
+                .unmatched
+                .values()
+                .map(|counts| counts.bytes)
+                .sum::<u64>() as usize,
+        ),
+    ];
+
+    render(1, header, rows, Some(total), config)
+}
+
+pub fn make_diff_table(diff: &DiffOutput, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = std::iter::once("")
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = diff
+        .languages
+        .iter()
+        .map(|language| {
+            std::iter::once(config.languages[language.lang_id].name.clone())
+                .chain(delta_cells(&columns, &language.old, &language.new))
+                .collect()
+        })
+        .collect();
+
+    let (old, new) = diff.total();
+    let total = std::iter::once("Total".to_string())
+        .chain(delta_cells(&columns, &old, &new))
+        .collect();
+
+    render(1, header, rows, Some(total), config)
+}
+
+pub fn make_file_diff_table(files: &[FileDiff], config: &Config) -> String {
+    // skip the `files` column like in `make_file_table`
+    let columns = &columns(config)[1..];
+
+    let header = ["file", "language"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = files
+        .iter()
+        .map(|file| {
+            [
+                display_path(&file.path, config),
+                config.languages[file.lang_id].name.clone(),
+            ]
+            .into_iter()
+            .chain(delta_cells(columns, &file.old, &file.new))
+            .collect()
+        })
+        .collect();
+
+    render(2, header, rows, None, config)
+}
+
+// commits are abbreviated like with `git log --abbrev=8`
+const SHORT_COMMIT_LEN: usize = 8;
+
+// one row per commit with the totals of all languages
+pub fn make_history_table(history: &HistoryOutput, config: &Config) -> String {
+    let columns = columns(config);
+
+    let header = ["date", "commit"]
+        .into_iter()
+        .chain(columns.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+
+    let rows = history
+        .snapshots
+        .iter()
+        .map(|snapshot| {
+            let commit = &snapshot.commit[..SHORT_COMMIT_LEN.min(snapshot.commit.len())];
+            [format_date(snapshot.time), commit.to_string()]
+                .into_iter()
+                .chain(count_cells(&columns, &snapshot.total()))
+                .collect()
+        })
+        .collect();
+
+    render(2, header, rows, None, config)
+}
@@ -0,0 +1,56 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::{
+    count::{Config, Counts, OutputCounts},
+    languages::LanguageId,
+};
+
+#[derive(Default)]
+pub struct DirNode {
+    pub counts: Counts,
+    pub languages: HashMap<LanguageId, Counts>,
+    pub children: BTreeMap<String, DirNode>,
+}
+
+impl DirNode {
+    fn add(&mut self, lang_id: LanguageId, counts: &Counts) {
+        self.counts.merge(counts);
+        self.languages.entry(lang_id).or_default().merge(counts);
+    }
+
+    // reverse order by number of code lines, forward order by name
+    pub fn sorted_children(&self) -> Vec<(&String, &DirNode)> {
+        let mut children = self.children.iter().collect::<Vec<_>>();
+        // stable sort, children are already ordered by name
+        children.sort_by_key(|(_, node)| Reverse(node.counts.code));
+        children
+    }
+}
+
+// aggregate per-file counts into a tree of directories rooted at `Config::abs_root`,
+// files deeper than `max_depth` are counted in their ancestor at `max_depth`
+pub fn make_tree(output: &OutputCounts, config: &Config, max_depth: usize) -> DirNode {
+    let mut root = DirNode::default();
+
+    for file in &output.files {
+        let rel_path = file
+            .path
+            .strip_prefix(&config.abs_root)
+            .unwrap_or(Path::new(""));
+        let dirs = rel_path.parent().into_iter().flat_map(Path::components);
+
+        let mut node = &mut root;
+        node.add(file.lang_id, &file.counts);
+        for dir in dirs.take(max_depth) {
+            let name = dir.as_os_str().to_string_lossy().into_owned();
+            node = node.children.entry(name).or_default();
+            node.add(file.lang_id, &file.counts);
+        }
+    }
+
+    root
+}
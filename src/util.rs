@@ -11,3 +11,77 @@ pub fn format_number(num: usize) -> String {
     }
     out
 }
+
+pub fn delta(old: usize, new: usize) -> i64 {
+    new as i64 - old as i64
+}
+
+// the change from `old` to `new` in percent, `None` if `old` is 0 but `new` isn't
+pub fn percent_change(old: usize, new: usize) -> Option<f64> {
+    if old == new {
+        Some(0.0)
+    } else if old == 0 {
+        None
+    } else {
+        Some((new as f64 - old as f64) / old as f64 * 100.0)
+    }
+}
+
+// e.g. "+1,234 (+5.2%)", relative to `old`
+pub fn format_delta(old: usize, new: usize) -> String {
+    if old == new {
+        return "0".to_string();
+    }
+
+    let sign = if new < old { '-' } else { '+' };
+    let delta = format_number(old.abs_diff(new));
+    match percent_change(old, new) {
+        Some(percent) => format!("{sign}{delta} ({percent:+.1}%)"),
+        None => format!("{sign}{delta} (new)"),
+    }
+}
+
+// (year, month, day) of a number of days since 1970-01-01, Howard Hinnant's
+// `civil_from_days` algorithm
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097); // [0, 146096]
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365; // [0, 399]
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // [0, 365]
+    let mp = (5 * doy + 2) / 153; // [0, 11], starting in March
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// e.g. "2024-03-15", in UTC, `timestamp` is in seconds since the epoch
+pub fn format_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(-165), (1969, 7, 20));
+        assert_eq!(civil_from_days(-25508), (1900, 3, 1));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(1706745599), "2024-01-31");
+        assert_eq!(format_date(1706745600), "2024-02-01");
+        assert_eq!(format_date(-1), "1969-12-31");
+    }
+}